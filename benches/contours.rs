@@ -0,0 +1,32 @@
+//! Content detection on a dense synthetic page; compare with and without `--features parallel`
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, GrayImage, Luma};
+use resnap_rs::{detect_content, CropOptions};
+
+/// A 1404x1872 page covered in small strokes, one contour each
+fn dense_page() -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(1404, 1872, |x, y| {
+        if x % 12 < 6 && y % 12 < 6 {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    }))
+}
+
+fn bench_detect_content(c: &mut Criterion) {
+    let page = dense_page();
+    let opts = CropOptions {
+        min_contour_size: 0,
+        ui_exclude: None,
+        ..CropOptions::default()
+    };
+
+    c.bench_function("detect_content dense page", |b| {
+        b.iter(|| detect_content(&page, &opts))
+    });
+}
+
+criterion_group!(benches, bench_detect_content);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+use crate::{output::flatten_onto_white, ResnapError};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame,
+};
+use std::{fs::File, io::BufWriter, path::Path, time::Duration};
+
+/// Speed of the GIF color quantization, from 1 (best colors) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Animated GIF written one frame at a time, so that only the current frame is kept in memory
+///
+/// Frames are flattened onto white and shown for the same delay; the animation loops forever.
+/// The file is complete once the animation is dropped or [`finish`](Self::finish)ed.
+pub struct GifAnimation {
+    encoder: GifEncoder<BufWriter<File>>,
+    delay: Delay,
+    frames: usize,
+}
+
+impl GifAnimation {
+    /// Fail with [`ResnapError::InvalidConfig`] unless `path` ends in `.gif`
+    pub fn check_path(path: &Path) -> Result<(), ResnapError> {
+        let is_gif = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+        if !is_gif {
+            return Err(ResnapError::InvalidConfig(format!(
+                "animation {} must be a .gif file",
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Create the animation at `path`, which must end in `.gif`
+    ///
+    /// An existing file at `path` is overwritten.
+    pub fn create(path: &Path, delay: Duration) -> Result<Self, ResnapError> {
+        Self::check_path(path)?;
+
+        let mut encoder =
+            GifEncoder::new_with_speed(BufWriter::new(File::create(path)?), GIF_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        Ok(Self {
+            encoder,
+            delay: Delay::from_saturating_duration(delay),
+            frames: 0,
+        })
+    }
+
+    /// Encode `img` as the next frame
+    pub fn add_frame(&mut self, img: &DynamicImage) -> Result<(), ResnapError> {
+        let rgba = DynamicImage::ImageRgb8(flatten_onto_white(img)).to_rgba8();
+        self.encoder
+            .encode_frame(Frame::from_parts(rgba, 0, 0, self.delay))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames encoded so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Write the end of the animation and close the file
+    pub fn finish(self) {
+        drop(self.encoder);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{
+    validate_host, Backend, CaptureConfig, CropOptions, DecodeOptions, Decoder, KnownHosts, Model,
+    ResnapError, RetryPolicy, Rotation, ThresholdMode, Transfer, UiMask,
+};
+use std::{path::PathBuf, time::Duration};
+
+/// Chainable builder for a [`CaptureConfig`] with the same defaults as the command line
+///
+/// ```no_run
+/// # async fn run() -> Result<(), resnap_rs::ResnapError> {
+/// let config = resnap_rs::CaptureConfigBuilder::new("10.11.99.1")
+///     .decoder(resnap_rs::Decoder::Native)
+///     .padding(20)
+///     .build()?;
+/// let cropped = resnap_rs::capture(&config).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CaptureConfigBuilder {
+    config: CaptureConfig,
+    crop: CropOptions,
+    crop_enabled: bool,
+}
+
+impl CaptureConfigBuilder {
+    /// Start a configuration for the tablet at `ip_address`
+    pub fn new(ip_address: impl Into<String>) -> Self {
+        Self {
+            config: CaptureConfig {
+                ip_address: ip_address.into(),
+                user: None,
+                port: None,
+                ssh_config: None,
+                known_hosts: KnownHosts::Add,
+                timeout: Some(Duration::from_secs(15)),
+                retry: RetryPolicy::default(),
+                identity: None,
+                jump: None,
+                model: None,
+                wake: false,
+                backend: Backend::default(),
+                framebuffer: 0,
+                transfer: Transfer::default(),
+                chunk_size: None,
+                compress: false,
+                decode: DecodeOptions::default(),
+                crop: None,
+                keep_raw: None,
+            },
+            crop: CropOptions::default(),
+            crop_enabled: true,
+        }
+    }
+
+    /// IP address of the tablet
+    pub fn ip(mut self, ip_address: impl Into<String>) -> Self {
+        self.config.ip_address = ip_address.into();
+        self
+    }
+
+    /// SSH login user, instead of the SSH config's or `root`
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.config.user = Some(user.into());
+        self
+    }
+
+    /// SSH port, instead of the SSH config's or 22
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = Some(port);
+        self
+    }
+
+    /// SSH config file to read instead of `~/.ssh/config`
+    pub fn ssh_config(mut self, ssh_config: impl Into<PathBuf>) -> Self {
+        self.config.ssh_config = Some(ssh_config.into());
+        self
+    }
+
+    /// How the tablet's host key is checked
+    pub fn known_hosts(mut self, known_hosts: KnownHosts) -> Self {
+        self.config.known_hosts = known_hosts;
+        self
+    }
+
+    /// Time limit for connecting and each step on the tablet; `None` waits forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Retries of connection failures and timeouts
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    /// SSH private key used instead of the default identities
+    pub fn identity(mut self, identity: impl Into<PathBuf>) -> Self {
+        self.config.identity = Some(identity.into());
+        self
+    }
+
+    /// Jump host `[user@]host[:port]` to tunnel the connection through
+    pub fn jump(mut self, jump: impl Into<String>) -> Self {
+        self.config.jump = Some(jump.into());
+        self
+    }
+
+    /// Tablet model, skipping detection over SSH
+    pub fn model(mut self, model: Model) -> Self {
+        self.config.model = Some(model);
+        self
+    }
+
+    /// Wake the display before capturing
+    pub fn wake(mut self, wake: bool) -> Self {
+        self.config.wake = wake;
+        self
+    }
+
+    /// Where the screen is read from on the tablet
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Capture the framebuffer device `/dev/fb<n>` instead of `/dev/fb0`
+    pub fn framebuffer(mut self, n: u32) -> Self {
+        self.config.framebuffer = n;
+        self
+    }
+
+    /// How the framebuffer is copied off the tablet
+    pub fn transfer(mut self, transfer: Transfer) -> Self {
+        self.config.transfer = transfer;
+        self
+    }
+
+    /// Read the framebuffer with a separate `dd` for each block of `chunk_size` bytes instead of
+    /// all at once; sizes below [`MIN_CHUNK_SIZE`](crate::MIN_CHUNK_SIZE) are raised to it
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.config.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Compress the framebuffer on the tablet before transferring it
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.config.compress = compress;
+        self
+    }
+
+    /// Backend used to convert the raw framebuffer
+    pub fn decoder(mut self, decoder: Decoder) -> Self {
+        self.config.decode.decoder = decoder;
+        self
+    }
+
+    /// Input level (0-1) mapped to black by the contrast curve, instead of the model's default
+    pub fn black_point(mut self, black_point: f32) -> Self {
+        self.config.decode.black_point = Some(black_point);
+        self
+    }
+
+    /// Input level (0-1) mapped to white by the contrast curve, instead of the model's default
+    pub fn white_point(mut self, white_point: f32) -> Self {
+        self.config.decode.white_point = Some(white_point);
+        self
+    }
+
+    /// Skip the contrast curve and keep the framebuffer's raw levels
+    pub fn no_curves(mut self, no_curves: bool) -> Self {
+        self.config.decode.no_curves = no_curves;
+        self
+    }
+
+    /// Clockwise rotation of the screen, for landscape notebooks
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.config.decode.rotation = rotation;
+        self
+    }
+
+    /// Crop to the handwritten content, or keep the full screen
+    pub fn crop(mut self, crop: bool) -> Self {
+        self.crop_enabled = crop;
+        self
+    }
+
+    /// Grayscale threshold below which pixels count as handwriting
+    pub fn threshold(mut self, threshold: u8) -> Self {
+        self.crop.threshold = threshold;
+        self
+    }
+
+    /// Whether the threshold is used directly or computed per image
+    pub fn threshold_mode(mut self, threshold_mode: ThresholdMode) -> Self {
+        self.crop.threshold_mode = threshold_mode;
+        self
+    }
+
+    /// Gamma applied before thresholding; above 1 darkens midtones
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        self.crop.gamma = gamma;
+        self
+    }
+
+    /// Stretch the levels before thresholding, clipping `clip` percent of pixels at each end
+    pub fn auto_contrast(mut self, clip: f32) -> Self {
+        self.crop.auto_contrast = Some(clip);
+        self
+    }
+
+    /// Remove specks smaller than a square kernel of this radius
+    pub fn denoise(mut self, radius: u8) -> Self {
+        self.crop.denoise = Some(radius);
+        self
+    }
+
+    /// Minimum number of points for a contour to count as content
+    pub fn min_contour_size(mut self, min_contour_size: usize) -> Self {
+        self.crop.min_contour_size = min_contour_size;
+        self
+    }
+
+    /// How the tablet's UI is kept out of detection
+    pub fn mask_ui(mut self, mask_ui: UiMask) -> Self {
+        self.crop.mask_ui = mask_ui;
+        self
+    }
+
+    /// Top-left region covering the menu button to ignore with [`UiMask::Region`]; `None`
+    /// ignores nothing
+    pub fn ui_exclude(mut self, ui_exclude: Option<(u32, u32)>) -> Self {
+        self.crop.ui_exclude = ui_exclude;
+        self
+    }
+
+    /// Pixels of padding around the cropped content
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.crop.padding = padding;
+        self
+    }
+
+    /// Trim white borders off the cropped image, counting pixels within `tolerance` levels of
+    /// white as white
+    pub fn trim(mut self, tolerance: u8) -> Self {
+        self.crop.trim = Some(tolerance);
+        self
+    }
+
+    /// Detect light writing on a dark background
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.crop.invert = invert;
+        self
+    }
+
+    /// File to keep the raw framebuffer dump in
+    pub fn keep_raw(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.keep_raw = Some(path.into());
+        self
+    }
+
+    /// Validate the settings and return the configuration
+    pub fn build(self) -> Result<CaptureConfig, ResnapError> {
+        validate_host(&self.config.ip_address)?;
+        self.config.decode.validate()?;
+        self.crop.validate()?;
+
+        Ok(self.build_unchecked())
+    }
+
+    /// Return the configuration without validating it, for an already open session
+    pub(crate) fn build_unchecked(self) -> CaptureConfig {
+        CaptureConfig {
+            crop: self.crop_enabled.then_some(self.crop),
+            ..self.config
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::ResnapError;
+use arboard::{Clipboard, ImageData};
+use image::DynamicImage;
+use std::borrow::Cow;
+
+/// Copy an image to the system clipboard as image data
+///
+/// On Linux the clipboard belongs to the process that set it, so the image is handed over to
+/// the desktop's clipboard manager when the [`Clipboard`] is dropped at the end of this call;
+/// without one it's gone from the clipboard then.
+pub fn copy_to_clipboard(img: &DynamicImage) -> Result<(), ResnapError> {
+    let rgba = img.to_rgba8();
+    let image = ImageData {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        bytes: Cow::Owned(rgba.into_raw()),
+    };
+
+    Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_image(image))
+        .map_err(|e| ResnapError::Clipboard(e.to_string()))?;
+
+    log::info!("📋 Copied image to the clipboard");
+    Ok(())
+}
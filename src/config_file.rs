@@ -0,0 +1,99 @@
+use crate::ResnapError;
+use clap::{parser::ValueSource, ArgMatches, Command};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the config file looked up in the working directory and config directory
+pub const CONFIG_FILE_NAME: &str = "resnap.toml";
+
+/// Find the config file to read defaults from
+///
+/// An explicit path is used as is; otherwise `resnap.toml` is looked up in the working
+/// directory, then in `$XDG_CONFIG_HOME/resnap/` (`~/.config/resnap/` when unset).
+pub fn find_config_file(explicit: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = explicit {
+        return Some(path.to_path_buf());
+    }
+
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    std::iter::once(PathBuf::from(CONFIG_FILE_NAME))
+        .chain(config_dir.map(|dir| dir.join("resnap").join(CONFIG_FILE_NAME)))
+        .find(|path| path.is_file())
+}
+
+/// Turn the options in a config file into command line arguments for `cmd`
+///
+/// Keys are the long option names, such as `ip-address = "10.11.99.1"` or `wake = true`.
+/// Options already given on the command line or by an environment variable in `matches`, or
+/// conflicting with one that was, are skipped so that both take precedence when the returned
+/// arguments are parsed before the command line.
+pub fn config_file_args(
+    path: &Path,
+    cmd: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, ResnapError> {
+    let invalid = |msg: String| ResnapError::InvalidConfig(format!("{}: {}", path.display(), msg));
+
+    let contents = fs::read_to_string(path)?;
+    let table: toml::Table = contents.parse().map_err(|e| invalid(e.to_string()))?;
+
+    let given_on_cli = |arg: &clap::Arg| {
+        matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        let arg = cmd
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()))
+            .ok_or_else(|| invalid(format!("unknown option {:?}", key)))?;
+
+        // Conflicts are declared on one side only, so check both directions
+        let conflicts_with_cli = cmd.get_arguments().any(|given| {
+            given_on_cli(given)
+                && (cmd
+                    .get_arg_conflicts_with(given)
+                    .iter()
+                    .any(|c| c.get_id() == arg.get_id())
+                    || cmd
+                        .get_arg_conflicts_with(arg)
+                        .iter()
+                        .any(|c| c.get_id() == given.get_id()))
+        });
+        if given_on_cli(arg) || conflicts_with_cli {
+            continue;
+        }
+
+        let flag = format!("--{}", key);
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => args.push(OsString::from(&flag)),
+                // Flags can't be switched off, so false just leaves them unset
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(s) => args.extend([OsString::from(&flag), s.into()]),
+                toml::Value::Integer(n) => {
+                    args.extend([OsString::from(&flag), n.to_string().into()])
+                }
+                toml::Value::Float(n) => args.extend([OsString::from(&flag), n.to_string().into()]),
+                _ => return Err(invalid(format!("unsupported value for {:?}", key))),
+            }
+        }
+    }
+
+    Ok(args)
+}
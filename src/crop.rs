@@ -0,0 +1,786 @@
+use crate::ResnapError;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use imageproc::{contours, contrast, distance_transform::Norm, morphology, point::Point};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::str::FromStr;
+
+/// How the grayscale threshold separating handwriting from background is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ThresholdMode {
+    /// Use the configured threshold as is
+    #[default]
+    Fixed,
+    /// Compute the threshold from the image histogram with Otsu's method
+    Otsu,
+}
+
+/// How the tablet's UI is kept out of content detection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum UiMask {
+    /// Find solid gray bands, such as an open toolbar, along the edges of the screen and
+    /// ignore them
+    Auto,
+    /// Ignore nothing
+    None,
+    /// Ignore the fixed top-left region [`CropOptions::ui_exclude`] covering the menu button
+    #[default]
+    Region,
+}
+
+/// Options controlling how handwritten content is detected and cropped
+#[derive(Debug, Clone)]
+pub struct CropOptions {
+    /// Grayscale values below this are treated as handwriting
+    pub threshold: u8,
+    /// Whether `threshold` is used directly or computed per image
+    pub threshold_mode: ThresholdMode,
+    /// Gamma applied to the grayscale image before thresholding; above 1 darkens midtones so
+    /// faint pencil passes the threshold, 1 leaves the image unchanged
+    pub gamma: f32,
+    /// Stretch the histogram before thresholding so that this percentage of pixels at each end
+    /// is clipped to black and white; `None` leaves the levels as captured
+    pub auto_contrast: Option<f32>,
+    /// Radius of the square kernel used to remove speckle noise before finding contours, if any
+    pub denoise: Option<u8>,
+    /// Contours with fewer points than this are ignored as noise; 0 keeps all of them
+    pub min_contour_size: usize,
+    /// Whether to draw the significant contours into [`ContentDetection::contour_vis`]
+    pub visualize_contours: bool,
+    /// Treat the page as light writing on a dark background, as in dark mode
+    pub invert: bool,
+    /// How the tablet's UI is kept out of detection
+    pub mask_ui: UiMask,
+    /// Top-left region `(x, y)` covering the menu button that is ignored with
+    /// [`UiMask::Region`], if any
+    pub ui_exclude: Option<(u32, u32)>,
+    /// Pixels of padding added around the content bounding box
+    pub padding: u32,
+    /// Trim white borders off the cropped image, counting pixels within this many levels of
+    /// white as white; `None` keeps the padded bounding box
+    pub trim: Option<u8>,
+    /// Crop every capture to this rectangle instead of the detected content, so that a series of
+    /// captures shares the same dimensions; `trim` is not applied to it and it must lie within
+    /// the screen
+    pub fixed_rect: Option<Rect>,
+    /// Crop straight to this rectangle of the rotated screen without detecting content, for
+    /// fixed layouts such as templates; it must lie within the screen
+    pub region: Option<Rect>,
+}
+
+impl Default for CropOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 200,
+            threshold_mode: ThresholdMode::Fixed,
+            gamma: 1.0,
+            auto_contrast: None,
+            denoise: None,
+            min_contour_size: 100,
+            visualize_contours: false,
+            invert: false,
+            mask_ui: UiMask::default(),
+            ui_exclude: Some((200, 200)),
+            padding: 50,
+            trim: None,
+            fixed_rect: None,
+            region: None,
+        }
+    }
+}
+
+impl CropOptions {
+    /// Check that the gamma is positive, the contrast clip leaves some pixels unclipped and the
+    /// fixed crop or region isn't empty
+    pub fn validate(&self) -> Result<(), ResnapError> {
+        if !(self.gamma.is_finite() && self.gamma > 0.0) {
+            return Err(ResnapError::InvalidConfig(format!(
+                "gamma ({}) must be greater than 0",
+                self.gamma
+            )));
+        }
+        if let Some(clip) = self.auto_contrast {
+            if !(0.0..50.0).contains(&clip) {
+                return Err(ResnapError::InvalidConfig(format!(
+                    "auto-contrast clip ({}%) must be at least 0 and below 50",
+                    clip
+                )));
+            }
+        }
+        if let Some(rect) = self.fixed_rect {
+            if rect.width == 0 || rect.height == 0 {
+                return Err(ResnapError::InvalidConfig(format!(
+                    "fixed crop {}x{} must not be empty",
+                    rect.width, rect.height
+                )));
+            }
+        }
+        if let Some(rect) = self.region {
+            if rect.width == 0 || rect.height == 0 {
+                return Err(ResnapError::InvalidConfig(format!(
+                    "region {}x{} must not be empty",
+                    rect.width, rect.height
+                )));
+            }
+            if self.fixed_rect.is_some() {
+                return Err(ResnapError::InvalidConfig(
+                    "a region and a fixed crop can't both be set".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rectangle in image coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Whether the pixel at `(x, y)` lies inside the rectangle
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+}
+
+impl FromStr for Rect {
+    type Err = ResnapError;
+
+    /// Parse `x,y,width,height`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || ResnapError::InvalidConfig(format!("crop {:?} is not x,y,width,height", s));
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match values[..] {
+            [x, y, width, height] => Ok(Self {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Outcome of searching a screen image for handwritten content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDetection {
+    /// Number of contours found in the binarized image
+    pub contours_found: usize,
+    /// Number of contours large enough to count as content
+    pub contours_significant: usize,
+    /// Padded bounding box of the significant contours; `None` when nothing was found
+    pub bounding_box: Option<Rect>,
+    /// Points of each significant contour, in image coordinates
+    pub contours: Vec<Vec<Point<i32>>>,
+    /// Significant contours drawn in red on white, if requested with
+    /// [`CropOptions::visualize_contours`]
+    pub contour_vis: Option<RgbaImage>,
+}
+
+/// Detect the handwritten content in a screen image and crop to it
+///
+/// Bright pixels in the cropped image are made transparent. Returns `None` when
+/// no significant content is found.
+pub fn crop_to_content(img: &DynamicImage, opts: &CropOptions) -> Option<DynamicImage> {
+    detect_content(img, opts)
+        .bounding_box
+        .map(|rect| crop_to_rect(img, rect, opts))
+}
+
+/// Find the handwritten content in a screen image and its padded bounding box
+pub fn detect_content(img: &DynamicImage, opts: &CropOptions) -> ContentDetection {
+    detect_gray_content(img.to_luma8(), opts)
+}
+
+/// Padded bounding box of the handwritten content in a grayscale screen image, or `None` when
+/// no significant content is found
+///
+/// Runs the same thresholding, contour filtering and padding as [`detect_content`] on a copy of
+/// `gray`, without any tablet or files involved.
+pub fn content_bounding_box(gray: &GrayImage, opts: &CropOptions) -> Option<Rect> {
+    let opts = CropOptions {
+        visualize_contours: false,
+        ..opts.clone()
+    };
+    detect_gray_content(gray.clone(), &opts).bounding_box
+}
+
+/// Find the handwritten content in a grayscale image, which is thresholded in place
+fn detect_gray_content(mut gray_img: GrayImage, opts: &CropOptions) -> ContentDetection {
+    // Flip dark-mode pages so the writing is dark on light like everywhere else
+    if opts.invert {
+        image::imageops::invert(&mut gray_img);
+    }
+
+    // Regions of the screen taken up by the UI, found before the levels are adjusted
+    let ui_masks: Vec<Rect> = match opts.mask_ui {
+        UiMask::None => Vec::new(),
+        UiMask::Region => opts
+            .ui_exclude
+            .map(|(width, height)| Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            })
+            .into_iter()
+            .collect(),
+        UiMask::Auto => {
+            let bands = ui_bands(&gray_img);
+            for band in &bands {
+                log::info!(
+                    "🧰 Ignoring UI band at {},{},{},{}",
+                    band.x,
+                    band.y,
+                    band.width,
+                    band.height
+                );
+            }
+            bands
+        }
+    };
+
+    // Even out brightness drift between captures before any fixed threshold is applied
+    if let Some(clip) = opts.auto_contrast {
+        let lut = stretch_lut(&gray_img, clip);
+        apply_lut(&mut gray_img, &lut);
+    }
+
+    if opts.gamma != 1.0 {
+        apply_lut(&mut gray_img, &gamma_lut(opts.gamma));
+    }
+
+    // Threshold to isolate handwriting (assuming dark writing on light background)
+    let threshold = match opts.threshold_mode {
+        ThresholdMode::Fixed => opts.threshold,
+        ThresholdMode::Otsu => {
+            // Otsu's level is the brightest value of the dark class, so include it
+            let level = contrast::otsu_level(&gray_img).saturating_add(1);
+            log::info!("🎚️ Otsu threshold: {}", level);
+            level
+        }
+    };
+
+    // Binarize in place to isolate the handwriting, reusing the grayscale buffer
+    let (width, height) = gray_img.dimensions();
+    let mut binary_img = gray_img;
+    for (x, y, pixel) in binary_img.enumerate_pixels_mut() {
+        *pixel = if ui_masks.iter().any(|mask| mask.contains(x, y)) {
+            Luma([255]) // Mark as background
+        } else if pixel.0[0] < threshold {
+            Luma([0]) // Black - this is handwriting
+        } else {
+            Luma([255]) // White - this is background
+        };
+    }
+
+    // Morphological opening of the handwriting removes specks smaller than the kernel.
+    // Strokes are black here, so this is a closing of the white background.
+    if let Some(k) = opts.denoise.filter(|&k| k > 0) {
+        morphology::close_mut(&mut binary_img, Norm::LInf, k);
+    }
+
+    // Find contours in the binary image
+    let contours = contours::find_contours::<i32>(&binary_img);
+
+    // Create visualization of contours for debugging, on a white background
+    let mut contour_vis = opts
+        .visualize_contours
+        .then(|| RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255])));
+
+    // Filter out small noise contours
+    let min_contour_size = opts.min_contour_size;
+    let found_contours = contours.len();
+    let significant: Vec<Vec<Point<i32>>> = contours
+        .into_iter()
+        .filter(|contour| contour.points.len() >= min_contour_size)
+        .map(|contour| contour.points)
+        .collect();
+    let large_contours = significant.len();
+
+    let in_image = |point: &&Point<i32>| {
+        point.x >= 0 && point.y >= 0 && point.x < width as i32 && point.y < height as i32
+    };
+
+    // Draw the contours for visualization
+    if let Some(vis) = contour_vis.as_mut() {
+        for point in significant.iter().flatten().filter(in_image) {
+            vis.put_pixel(point.x as u32, point.y as u32, Rgba([255, 0, 0, 255]));
+        }
+    }
+
+    // Calculate bounding box for all content of interest, per contour and then combined
+    let contour_bounds = |points: &Vec<Point<i32>>| {
+        points.iter().filter(in_image).fold(None, |bounds, point| {
+            let (x, y) = (point.x as u32, point.y as u32);
+            merge_bounds(bounds, Some((x, y, x, y)))
+        })
+    };
+    #[cfg(feature = "parallel")]
+    let bounds = significant
+        .par_iter()
+        .map(contour_bounds)
+        .reduce(|| None, merge_bounds);
+    #[cfg(not(feature = "parallel"))]
+    let bounds = significant
+        .iter()
+        .map(contour_bounds)
+        .fold(None, merge_bounds);
+    let (min_x, min_y, max_x, max_y) = bounds.unwrap_or((width, height, 0, 0));
+
+    log::info!(
+        "Found {} contours, {} significant (at least {} points)",
+        found_contours,
+        large_contours,
+        min_contour_size
+    );
+
+    // Add padding to the bounding box, clamped to the image bounds
+    let padding = opts.padding;
+    let min_x = min_x.saturating_sub(padding);
+    let min_y = min_y.saturating_sub(padding);
+    let max_x = max_x.saturating_add(padding).min(width - 1);
+    let max_y = max_y.saturating_add(padding).min(height - 1);
+
+    // If we found a valid bounding box (content of interest)
+    let bounding_box = if min_x < max_x && min_y < max_y && large_contours > 0 {
+        let width = max_x - min_x + 1;
+        let height = max_y - min_y + 1;
+
+        log::info!(
+            "📏 Content bounding box: ({}, {}) to ({}, {}), size: {}x{}",
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            width,
+            height
+        );
+
+        Some(Rect {
+            x: min_x,
+            y: min_y,
+            width,
+            height,
+        })
+    } else {
+        log::info!("⚠️ No significant content found in the image");
+        None
+    };
+
+    ContentDetection {
+        contours_found: found_contours,
+        contours_significant: large_contours,
+        bounding_box,
+        contours: significant,
+        contour_vis,
+    }
+}
+
+/// Smallest box `(min_x, min_y, max_x, max_y)` containing both boxes
+fn merge_bounds(
+    a: Option<(u32, u32, u32, u32)>,
+    b: Option<(u32, u32, u32, u32)>,
+) -> Option<(u32, u32, u32, u32)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Lines of pixels narrower than this along an edge are taken for borders, not UI
+const MIN_UI_BAND: u32 = 8;
+
+/// Gray levels that a UI band may be filled with, excluding paper white and ink black
+const UI_BAND_LEVELS: std::ops::RangeInclusive<usize> = 32..=224;
+
+/// Find solid gray bands along the edges of a grayscale screen, such as xochitl's toolbar
+///
+/// A column or row belongs to a band when at least 80% of its pixels are within a few levels of
+/// its most common gray, so the icons drawn on a toolbar don't break it up. Bands are grown
+/// inwards from each edge, up to a quarter of the screen.
+fn ui_bands(gray: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Vec<Rect> {
+    let is_solid_gray = |pixels: &mut dyn Iterator<Item = u8>, len: u32| {
+        let mut histogram = [0u32; 256];
+        for pixel in pixels {
+            histogram[usize::from(pixel)] += 1;
+        }
+        let level = (0..256).max_by_key(|&v| histogram[v]).unwrap_or(255);
+        let near: u32 = histogram[level.saturating_sub(4)..=(level + 4).min(255)]
+            .iter()
+            .sum();
+        UI_BAND_LEVELS.contains(&level) && u64::from(near) * 5 >= u64::from(len) * 4
+    };
+
+    let (width, height) = gray.dimensions();
+    let column =
+        |x: u32| is_solid_gray(&mut (0..height).map(|y| gray.get_pixel(x, y).0[0]), height);
+    let row = |y: u32| is_solid_gray(&mut (0..width).map(|x| gray.get_pixel(x, y).0[0]), width);
+    let band = |solid: &dyn Fn(u32) -> bool, len: u32| {
+        (0..len / 4).take_while(|&i| solid(i)).count() as u32
+    };
+
+    let left = band(&column, width);
+    let right = band(&|i| column(width - 1 - i), width);
+    let top = band(&row, height);
+    let bottom = band(&|i| row(height - 1 - i), height);
+
+    let rect = |x, y, width, height| Rect {
+        x,
+        y,
+        width,
+        height,
+    };
+    [
+        rect(0, 0, left, height),
+        rect(width - right, 0, right, height),
+        rect(0, 0, width, top),
+        rect(0, height - bottom, width, bottom),
+    ]
+    .into_iter()
+    .filter(|band| band.width.min(band.height) >= MIN_UI_BAND)
+    .collect()
+}
+
+/// Replace every gray level `v` with `lut[v]`
+fn apply_lut(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, lut: &[u8; 256]) {
+    for pixel in img.pixels_mut() {
+        pixel.0[0] = lut[usize::from(pixel.0[0])];
+    }
+}
+
+/// Lookup table stretching the levels between the `clip` and `100 - clip` percentiles to 0-255
+fn stretch_lut(img: &ImageBuffer<Luma<u8>, Vec<u8>>, clip: f32) -> [u8; 256] {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[usize::from(pixel.0[0])] += 1;
+    }
+
+    let total = u64::from(img.width()) * u64::from(img.height());
+    let clipped = (total as f64 * f64::from(clip) / 100.0) as u64;
+    let percentile = |levels: &mut dyn Iterator<Item = usize>| {
+        let mut seen = 0;
+        levels
+            .find(|&level| {
+                seen += histogram[level];
+                seen > clipped
+            })
+            .unwrap_or(0)
+    };
+    let low = percentile(&mut (0..256usize));
+    let high = percentile(&mut (0..256usize).rev());
+    log::info!("🌗 Stretching levels {}-{} to 0-255", low, high);
+
+    let mut lut = [0; 256];
+    if high <= low {
+        // A flat image has nothing to stretch
+        for (value, out) in lut.iter_mut().enumerate() {
+            *out = value as u8;
+        }
+        return lut;
+    }
+    for (value, out) in lut.iter_mut().enumerate() {
+        let stretched = (value.clamp(low, high) - low) as f32 * 255.0 / (high - low) as f32;
+        *out = stretched.round() as u8;
+    }
+    lut
+}
+
+/// Lookup table mapping each gray level `v` to `255 * (v / 255)^gamma`
+fn gamma_lut(gamma: f32) -> [u8; 256] {
+    let mut lut = [0; 256];
+    for (value, out) in lut.iter_mut().enumerate() {
+        *out = (255.0 * (value as f32 / 255.0).powf(gamma)).round() as u8;
+    }
+    lut
+}
+
+/// Crop an image to a rectangle, making the background pixels transparent
+///
+/// The background is the brightest pixels, or the darkest with [`CropOptions::invert`].
+pub fn crop_to_rect(img: &DynamicImage, rect: Rect, opts: &CropOptions) -> DynamicImage {
+    let rect = match opts.trim {
+        Some(tolerance) => trim_rect(img, rect, tolerance, opts.invert),
+        None => rect,
+    };
+
+    // Create cropped image, converted to RGBA to enable transparency without another copy
+    // when it already is
+    let mut rgba_img = img
+        .crop_imm(rect.x, rect.y, rect.width, rect.height)
+        .into_rgba8();
+
+    // Brightness threshold for transparency - adjust as needed
+    let transparency_threshold = 230;
+
+    // Make the background pixels transparent in place
+    for pixel in rgba_img.pixels_mut() {
+        // Calculate brightness (average of RGB values as a simple approach)
+        let mut brightness = (pixel[0] as u16 + pixel[1] as u16 + pixel[2] as u16) / 3;
+        if opts.invert {
+            brightness = 255 - brightness;
+        }
+
+        // Background pixels are transparent, the others keep full opacity
+        pixel[3] = if brightness >= transparency_threshold {
+            0
+        } else {
+            255
+        };
+    }
+
+    DynamicImage::ImageRgba8(rgba_img)
+}
+
+/// Shrink a rectangle so that it has no fully white rows or columns along its edges
+///
+/// Pixels within `tolerance` levels of white count as white, so faint antialiased edges of
+/// strokes are trimmed but the strokes themselves are kept. With `invert` black counts as
+/// white. A rectangle that is white throughout is returned unchanged.
+pub(crate) fn trim_rect(img: &DynamicImage, rect: Rect, tolerance: u8, invert: bool) -> Rect {
+    let gray = img
+        .crop_imm(rect.x, rect.y, rect.width, rect.height)
+        .to_luma8();
+    let is_ink = |x: u32, y: u32| {
+        let mut level = gray.get_pixel(x, y).0[0];
+        if invert {
+            level = 255 - level;
+        }
+        level < 255 - tolerance
+    };
+    let row_has_ink = |y: u32| (0..gray.width()).any(|x| is_ink(x, y));
+    let column_has_ink = |x: u32, y0: u32, y1: u32| (y0..=y1).any(|y| is_ink(x, y));
+
+    let Some(top) = (0..gray.height()).find(|&y| row_has_ink(y)) else {
+        return rect;
+    };
+    let bottom = (top..gray.height())
+        .rev()
+        .find(|&y| row_has_ink(y))
+        .unwrap_or(top);
+    let left = (0..gray.width())
+        .find(|&x| column_has_ink(x, top, bottom))
+        .unwrap_or(0);
+    let right = (left..gray.width())
+        .rev()
+        .find(|&x| column_has_ink(x, top, bottom))
+        .unwrap_or(left);
+
+    log::info!(
+        "✂️ Trimmed white borders: {}x{} to {}x{}",
+        rect.width,
+        rect.height,
+        right - left + 1,
+        bottom - top + 1
+    );
+
+    Rect {
+        x: rect.x + left,
+        y: rect.y + top,
+        width: right - left + 1,
+        height: bottom - top + 1,
+    }
+}
+
+/// Bounding boxes of separate pieces of content, for saving each region on its own
+///
+/// Each significant contour gets its own box; boxes closer than `gap` pixels are merged so a
+/// single drawing isn't split into fragments. The boxes are padded with `padding`, clamped to
+/// an image of `width` by `height`, and sorted top to bottom, then left to right.
+pub fn split_regions(
+    contours: &[Vec<Point<i32>>],
+    gap: u32,
+    padding: u32,
+    width: u32,
+    height: u32,
+) -> Vec<Rect> {
+    // Boxes as inclusive (min_x, min_y, max_x, max_y) corners
+    let mut boxes: Vec<(u32, u32, u32, u32)> = contours
+        .iter()
+        .filter_map(|points| {
+            let inside = points
+                .iter()
+                .filter(|p| p.x >= 0 && p.y >= 0 && (p.x as u32) < width && (p.y as u32) < height);
+            inside.fold(None, |acc: Option<(u32, u32, u32, u32)>, p| {
+                let (x, y) = (p.x as u32, p.y as u32);
+                Some(match acc {
+                    None => (x, y, x, y),
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                })
+            })
+        })
+        .collect();
+
+    // Merge boxes within `gap` of each other until none are left to merge
+    let near = |a: &(u32, u32, u32, u32), b: &(u32, u32, u32, u32)| {
+        a.0 <= b.2.saturating_add(gap)
+            && b.0 <= a.2.saturating_add(gap)
+            && a.1 <= b.3.saturating_add(gap)
+            && b.1 <= a.3.saturating_add(gap)
+    };
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'outer: for i in 0..boxes.len() {
+            for j in (i + 1)..boxes.len() {
+                if near(&boxes[i], &boxes[j]) {
+                    let b = boxes.swap_remove(j);
+                    let a = &mut boxes[i];
+                    *a = (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3));
+                    merged = true;
+                    break 'outer;
+                }
+            }
+        }
+    }
+
+    boxes.sort_by_key(|&(x0, y0, _, _)| (y0, x0));
+
+    boxes
+        .into_iter()
+        .map(|(x0, y0, x1, y1)| {
+            let x0 = x0.saturating_sub(padding);
+            let y0 = y0.saturating_sub(padding);
+            let x1 = x1.saturating_add(padding).min(width - 1);
+            let y1 = y1.saturating_add(padding).min(height - 1);
+            Rect {
+                x: x0,
+                y: y0,
+                width: x1 - x0 + 1,
+                height: y1 - y0 + 1,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A white 1000x1200 page with black squares, each given as its top-left corner and size
+    fn page_with_squares(squares: &[(u32, u32, u32)]) -> GrayImage {
+        GrayImage::from_fn(1000, 1200, |px, py| {
+            let inside = squares
+                .iter()
+                .any(|&(x, y, size)| (x..x + size).contains(&px) && (y..y + size).contains(&py));
+            Luma([if inside { 0 } else { 255 }])
+        })
+    }
+
+    fn page_with_square(x: u32, y: u32, size: u32) -> GrayImage {
+        page_with_squares(&[(x, y, size)])
+    }
+
+    fn padded(padding: u32) -> CropOptions {
+        CropOptions {
+            padding,
+            ..CropOptions::default()
+        }
+    }
+
+    #[test]
+    fn blank_page_has_no_bounding_box() {
+        let page = GrayImage::from_pixel(1000, 1200, Luma([255]));
+        assert_eq!(content_bounding_box(&page, &CropOptions::default()), None);
+    }
+
+    #[test]
+    fn bounding_box_surrounds_the_content_with_padding() {
+        let page = page_with_square(500, 600, 100);
+
+        // The contour follows the edge of the square, give or take a pixel
+        let rect = content_bounding_box(&page, &padded(0)).unwrap();
+        assert!(rect.x <= 500 && rect.x + rect.width >= 600 && rect.width <= 102);
+        assert!(rect.y <= 600 && rect.y + rect.height >= 700 && rect.height <= 102);
+
+        let padded_rect = content_bounding_box(&page, &padded(10)).unwrap();
+        assert_eq!(
+            padded_rect,
+            Rect {
+                x: rect.x - 10,
+                y: rect.y - 10,
+                width: rect.width + 20,
+                height: rect.height + 20,
+            }
+        );
+    }
+
+    #[test]
+    fn bounding_box_spans_separate_blobs() {
+        let page = page_with_squares(&[(300, 400, 80), (700, 900, 80)]);
+
+        let rect = content_bounding_box(&page, &padded(0)).unwrap();
+        assert!(rect.x <= 300 && rect.x + rect.width >= 780 && rect.width <= 482);
+        assert!(rect.y <= 400 && rect.y + rect.height >= 980 && rect.height <= 582);
+    }
+
+    #[test]
+    fn specks_alone_are_not_content() {
+        // Single dark pixels scattered over the page, each far below the minimum contour size
+        let page = GrayImage::from_fn(1000, 1200, |x, y| {
+            Luma([if x % 37 == 5 && y % 41 == 7 { 0 } else { 255 }])
+        });
+        assert_eq!(content_bounding_box(&page, &CropOptions::default()), None);
+    }
+
+    #[test]
+    fn padding_is_clamped_to_the_image() {
+        let unmasked = CropOptions {
+            mask_ui: UiMask::None,
+            ..padded(50)
+        };
+
+        let rect = content_bounding_box(&page_with_square(20, 20, 60), &unmasked).unwrap();
+        assert_eq!((rect.x, rect.y), (0, 0));
+
+        let rect = content_bounding_box(&page_with_square(920, 1120, 60), &unmasked).unwrap();
+        assert_eq!((rect.x + rect.width, rect.y + rect.height), (1000, 1200));
+    }
+
+    #[test]
+    fn menu_button_region_is_ignored() {
+        let page = page_with_square(50, 50, 100);
+        assert_eq!(content_bounding_box(&page, &CropOptions::default()), None);
+
+        let unmasked = CropOptions {
+            mask_ui: UiMask::None,
+            ..CropOptions::default()
+        };
+        assert!(content_bounding_box(&page, &unmasked).is_some());
+    }
+
+    #[test]
+    fn content_left_of_x_150_is_cropped_tightly() {
+        // One square below the menu button region, which is kept, and one inside it, which is
+        // ignored; both lie entirely left of x=150
+        let page = GrayImage::from_fn(1000, 1200, |x, y| {
+            let in_square =
+                |x0: u32, y0: u32| (x0..x0 + 80).contains(&x) && (y0..y0 + 80).contains(&y);
+            let ink = in_square(20, 600) || in_square(20, 20);
+            Luma([if ink { 0 } else { 255 }])
+        });
+        let opts = CropOptions {
+            padding: 0,
+            ..CropOptions::default()
+        };
+
+        // The contour follows the edge of the kept square, give or take a pixel
+        let rect = detect_content(&DynamicImage::ImageLuma8(page), &opts)
+            .bounding_box
+            .unwrap();
+        assert!(rect.x <= 20 && rect.x + rect.width >= 100 && rect.width <= 82);
+        assert!(rect.y <= 600 && rect.y + rect.height >= 680 && rect.height <= 82);
+    }
+}
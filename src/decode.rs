@@ -0,0 +1,454 @@
+use crate::{FramebufferFormat, Model, ResnapError};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Pixel, Rgb, Rgba};
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+/// Number of trailing ffmpeg stderr lines included in conversion errors
+const FFMPEG_STDERR_LINES: usize = 8;
+
+/// Share of dark pixels below which a decoded screen counts as clipped to white, or above
+/// `1 -` which it counts as clipped to black. The menu button alone keeps a real screen above
+/// it, even on a blank page.
+const CLIPPED_INK: f32 = 0.0001;
+
+/// Backends that can turn a raw framebuffer into an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Decoder {
+    /// Convert with an external ffmpeg process
+    #[default]
+    Ffmpeg,
+    /// Convert in pure Rust without ffmpeg
+    Native,
+}
+
+/// Clockwise rotation applied to the decoded screen, for notebooks used in landscape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Rotation {
+    /// Keep the portrait orientation
+    #[default]
+    #[value(name = "0")]
+    None,
+    /// Rotate 90° clockwise
+    #[value(name = "90")]
+    Cw90,
+    /// Rotate 180°
+    #[value(name = "180")]
+    Cw180,
+    /// Rotate 270° clockwise
+    #[value(name = "270")]
+    Cw270,
+}
+
+impl Rotation {
+    /// Rotate an image by this amount
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Rotation::None => img,
+            Rotation::Cw90 => img.rotate90(),
+            Rotation::Cw180 => img.rotate180(),
+            Rotation::Cw270 => img.rotate270(),
+        }
+    }
+}
+
+/// Settings for converting a raw framebuffer into an image
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// Backend used for the conversion
+    pub decoder: Decoder,
+    /// Input level mapped to black, as a fraction of full scale; `None` uses the model's
+    /// default from [`FramebufferFormat::curve`]
+    pub black_point: Option<f32>,
+    /// Input level mapped to white, as a fraction of full scale; `None` uses the model's
+    /// default from [`FramebufferFormat::curve`]
+    pub white_point: Option<f32>,
+    /// Skip the contrast curve and keep the framebuffer's raw levels, for tuning the curve
+    pub no_curves: bool,
+    /// Rotation applied after orienting the framebuffer
+    pub rotation: Rotation,
+    /// Extra ffmpeg filters appended to the chain, only supported by [`Decoder::Ffmpeg`]
+    pub filters: Option<String>,
+    /// Use `filters` instead of the contrast curve; the orientation filter is still applied
+    /// unless `filters` transposes, flips or rotates the image itself
+    pub replace_filters: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            decoder: Decoder::Ffmpeg,
+            black_point: None,
+            white_point: None,
+            no_curves: false,
+            rotation: Rotation::None,
+            filters: None,
+            replace_filters: false,
+        }
+    }
+}
+
+impl DecodeOptions {
+    /// Check that the curve points are in range and ordered, and that custom filters can be
+    /// applied
+    pub fn validate(&self) -> Result<(), ResnapError> {
+        if self.filters.is_some() && self.decoder != Decoder::Ffmpeg {
+            return Err(ResnapError::InvalidConfig(
+                "custom ffmpeg filters need the ffmpeg decoder".to_string(),
+            ));
+        }
+        if self.no_curves && (self.black_point.is_some() || self.white_point.is_some()) {
+            return Err(ResnapError::InvalidConfig(
+                "the black and white points need the contrast curve".to_string(),
+            ));
+        }
+        if self.replace_filters && self.filters.is_none() {
+            return Err(ResnapError::InvalidConfig(
+                "replacing the filters needs a filter chain".to_string(),
+            ));
+        }
+
+        // Unset points are checked against the model's defaults when decoding
+        let in_range = |point: Option<f32>| point.map_or(true, |p| (0.0..=1.0).contains(&p));
+        if !in_range(self.black_point) || !in_range(self.white_point) {
+            return Err(curve_error(
+                self.black_point.unwrap_or(0.0),
+                self.white_point.unwrap_or(1.0),
+            ));
+        }
+        match (self.black_point, self.white_point) {
+            (Some(black), Some(white)) if black >= white => Err(curve_error(black, white)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Black and white points of the contrast curve, using the defaults of `fb_format` for the
+    /// ones that aren't set
+    ///
+    /// With [`DecodeOptions::no_curves`] this is the identity curve, from 0 to 1.
+    pub fn curve(&self, fb_format: &FramebufferFormat) -> Result<(f32, f32), ResnapError> {
+        if self.no_curves {
+            return Ok((0.0, 1.0));
+        }
+        let (default_black, default_white) = fb_format.curve;
+        let black = self.black_point.unwrap_or(default_black);
+        let white = self.white_point.unwrap_or(default_white);
+
+        if black < white {
+            Ok((black, white))
+        } else {
+            Err(curve_error(black, white))
+        }
+    }
+}
+
+/// Error for contrast curve points that are out of range or out of order
+fn curve_error(black_point: f32, white_point: f32) -> ResnapError {
+    ResnapError::InvalidConfig(format!(
+        "black point ({}) and white point ({}) must be between 0 and 1 with black below white",
+        black_point, white_point
+    ))
+}
+
+/// Convert a raw framebuffer dump into an oriented, contrast-adjusted image
+pub fn decode_framebuffer(
+    raw: &[u8],
+    fb_format: &FramebufferFormat,
+    opts: &DecodeOptions,
+) -> Result<DynamicImage, ResnapError> {
+    opts.validate()?;
+    let curve = opts.curve(fb_format)?;
+
+    let img = match opts.decoder {
+        Decoder::Ffmpeg => decode_with_ffmpeg(raw, fb_format, opts, curve)?,
+        Decoder::Native => decode_native(raw, fb_format, curve)?,
+    };
+    // Custom filters that replace the curve may well leave the screen white or black on purpose
+    if !opts.no_curves && !opts.replace_filters {
+        warn_if_clipped(&img, curve);
+    }
+
+    Ok(opts.rotation.apply(img))
+}
+
+/// Warn when the contrast curve left the screen almost entirely white or black, which happens
+/// when the framebuffer's levels all fall outside the black and white points
+///
+/// Every fourth pixel of every fourth row is sampled, which is plenty to tell.
+fn warn_if_clipped(img: &DynamicImage, (black_point, white_point): (f32, f32)) {
+    let (mut dark, mut total) = (0usize, 0usize);
+    for y in (0..img.height()).step_by(4) {
+        for x in (0..img.width()).step_by(4) {
+            total += 1;
+            if img.get_pixel(x, y).to_luma().0[0] < 128 {
+                dark += 1;
+            }
+        }
+    }
+    if total == 0 {
+        return;
+    }
+
+    let ink = dark as f32 / total as f32;
+    if ink < CLIPPED_INK {
+        log::warn!(
+            "⚠️ The screen decoded almost entirely white, so the framebuffer is probably lighter \
+             than the white point ({}); try a higher --white-point",
+            white_point
+        );
+    } else if ink > 1.0 - CLIPPED_INK {
+        log::warn!(
+            "⚠️ The screen decoded almost entirely black, so the framebuffer is probably darker \
+             than the black point ({}); try a lower --black-point",
+            black_point
+        );
+    }
+}
+
+/// Filters that orient a framebuffer and apply the contrast curve or the custom filters
+fn ffmpeg_filters(
+    fb_format: &FramebufferFormat,
+    opts: &DecodeOptions,
+    (black_point, white_point): (f32, f32),
+) -> String {
+    if opts.no_curves && !opts.replace_filters {
+        return match &opts.filters {
+            Some(custom) => format!("{},{}", fb_format.transpose, custom),
+            None => fb_format.transpose.to_string(),
+        };
+    }
+    let curves = format!("curves=all={}/0 {}/1", black_point, white_point);
+
+    match (&opts.filters, opts.replace_filters) {
+        (Some(custom), true) => {
+            // A chain that orients the image itself takes the place of the orientation filter
+            let orients = ["transpose", "hflip", "vflip", "rotate"]
+                .iter()
+                .any(|filter| custom.contains(filter));
+            if orients {
+                custom.clone()
+            } else {
+                format!("{},{}", fb_format.transpose, custom)
+            }
+        }
+        (Some(custom), false) => format!("{},{},{}", fb_format.transpose, curves, custom),
+        (None, _) => format!("{},{}", fb_format.transpose, curves),
+    }
+}
+
+/// Convert a raw framebuffer using ffmpeg
+///
+/// The raw bytes are fed to ffmpeg's stdin and the image it produces is read back from stdout
+/// as an uncompressed PAM, so that no PNG is encoded only to be decoded again. Files are only
+/// encoded when the capture is saved.
+fn decode_with_ffmpeg(
+    raw: &[u8],
+    fb_format: &FramebufferFormat,
+    opts: &DecodeOptions,
+    curve: (f32, f32),
+) -> Result<DynamicImage, ResnapError> {
+    let filters = ffmpeg_filters(fb_format, opts, curve);
+    log::debug!("🎛️ ffmpeg filters: {}", filters);
+
+    // Convert raw framebuffer to an uncompressed image on ffmpeg's stdout
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-f",
+            "rawvideo",
+            "-pixel_format",
+            fb_format.pixel_format,
+            "-video_size",
+            &format!("{}x{}", fb_format.width, fb_format.height),
+            "-i",
+            "-",
+            "-vf",
+            &filters,
+            "-f",
+            "image2pipe",
+            "-vcodec",
+            "pam",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => ResnapError::FfmpegNotInstalled,
+            _ => ResnapError::Io(e),
+        })?;
+
+    // Write stdin from a separate thread so ffmpeg can't block on a full stdout pipe
+    let mut stdin = child.stdin.take().expect("ffmpeg stdin is piped");
+    let output = thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(raw));
+        let output = child.wait_with_output();
+        // ffmpeg may exit before consuming all input, so write errors are only reported via its status
+        let _ = writer.join();
+        output
+    })?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    if output.status.success() {
+        log::debug!("ffmpeg output:\n{}", stderr.trim_end());
+        log::info!("🖼️ Converted framebuffer to image");
+    } else {
+        // The last few lines of ffmpeg's output usually explain the failure
+        let lines = stderr.lines().collect::<Vec<_>>();
+        let tail = lines[lines.len().saturating_sub(FFMPEG_STDERR_LINES)..].join("\n");
+        return Err(ResnapError::Ffmpeg(format!(
+            "ffmpeg exited with {}:\n{}",
+            output.status, tail
+        )));
+    }
+
+    Ok(image::load_from_memory(&output.stdout)?)
+}
+
+/// Convert a raw framebuffer in pure Rust
+///
+/// Supports the `gray16`, `rgb565le` and `rgba` pixel formats and the `transpose`,
+/// `hflip`, `vflip` and `null` filters used by the known models.
+fn decode_native(
+    raw: &[u8],
+    fb_format: &FramebufferFormat,
+    (black_point, white_point): (f32, f32),
+) -> Result<DynamicImage, ResnapError> {
+    let (width, height) = (fb_format.width as u32, fb_format.height as u32);
+    let lut = curve_lut(black_point, white_point);
+    // 8-bit samples are scaled to 16 bits before the lookup
+    let curve_u8 = |value: u8| lut[usize::from(value) * 257];
+
+    if raw.len() < fb_format.window_bytes() {
+        return Err(ResnapError::Decode(format!(
+            "framebuffer is {} bytes, expected {}",
+            raw.len(),
+            fb_format.window_bytes()
+        )));
+    }
+
+    let pixels = raw[..fb_format.window_bytes()].chunks_exact(fb_format.bytes_per_pixel);
+
+    let img = match fb_format.pixel_format {
+        "gray16" | "gray16le" => {
+            let data = pixels
+                .map(|p| lut[usize::from(u16::from_le_bytes([p[0], p[1]]))])
+                .collect();
+            DynamicImage::ImageLuma8(to_buffer::<Luma<u8>>(width, height, data)?)
+        }
+        "rgb565le" => {
+            let data = pixels
+                .flat_map(|p| {
+                    let v = u16::from_le_bytes([p[0], p[1]]);
+                    // Expand 5/6/5 bit channels to 8 bits
+                    let r = ((v >> 11) & 0x1f) as u8;
+                    let g = ((v >> 5) & 0x3f) as u8;
+                    let b = (v & 0x1f) as u8;
+                    [
+                        (r << 3) | (r >> 2),
+                        (g << 2) | (g >> 4),
+                        (b << 3) | (b >> 2),
+                    ]
+                })
+                .map(curve_u8)
+                .collect();
+            DynamicImage::ImageRgb8(to_buffer::<Rgb<u8>>(width, height, data)?)
+        }
+        "rgba" => {
+            let data = pixels
+                .flat_map(|p| [curve_u8(p[0]), curve_u8(p[1]), curve_u8(p[2]), p[3]])
+                .collect();
+            DynamicImage::ImageRgba8(to_buffer::<Rgba<u8>>(width, height, data)?)
+        }
+        other => {
+            return Err(ResnapError::Decode(format!(
+                "pixel format {} is not supported by the native decoder",
+                other
+            )))
+        }
+    };
+
+    let img = apply_filters(img, fb_format.transpose)?;
+    log::info!("🖼️ Converted framebuffer to image");
+
+    Ok(img)
+}
+
+/// Wrap decoded samples in an image buffer of the given size
+fn to_buffer<P: image::Pixel<Subpixel = u8>>(
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+) -> Result<ImageBuffer<P, Vec<u8>>, ResnapError> {
+    ImageBuffer::from_raw(width, height, data)
+        .ok_or_else(|| ResnapError::Decode("framebuffer size mismatch".to_string()))
+}
+
+/// Apply an ffmpeg-style orientation filter chain such as `transpose=2,hflip`
+fn apply_filters(img: DynamicImage, filters: &str) -> Result<DynamicImage, ResnapError> {
+    filters.split(',').try_fold(img, |img, filter| {
+        Ok(match filter.trim() {
+            "null" => img,
+            "hflip" => img.fliph(),
+            "vflip" => img.flipv(),
+            "transpose=0" => img.rotate270().flipv(), // 90° counterclockwise and vertical flip
+            "transpose=1" => img.rotate90(),          // 90° clockwise
+            "transpose=2" => img.rotate270(),         // 90° counterclockwise
+            "transpose=3" => img.rotate90().flipv(),  // 90° clockwise and vertical flip
+            other => {
+                return Err(ResnapError::Decode(format!(
+                    "filter {} is not supported by the native decoder",
+                    other
+                )))
+            }
+        })
+    })
+}
+
+/// Map a 16-bit sample through the contrast curve
+///
+/// Matches ffmpeg's `curves` filter with two points: values below the black point become
+/// black, values above the white point become white, and values in between are linear.
+pub fn apply_curve(value: u16, black_point: f32, white_point: f32) -> u8 {
+    let x = value as f32 / u16::MAX as f32;
+    let y = ((x - black_point) / (white_point - black_point)).clamp(0.0, 1.0);
+    (y * 255.0).round() as u8
+}
+
+/// Build a lookup table of the contrast curve for every 16-bit sample
+fn curve_lut(black_point: f32, white_point: f32) -> Vec<u8> {
+    (0..=u16::MAX)
+        .map(|value| apply_curve(value, black_point, white_point))
+        .collect()
+}
+
+/// Decode a raw framebuffer dump saved with `--keep-raw` using the given model's layout
+pub fn decode_raw_file(
+    path: &Path,
+    model: Model,
+    opts: &DecodeOptions,
+) -> Result<DynamicImage, ResnapError> {
+    let fb_format = model.framebuffer();
+    let raw = fs::read(path)?;
+    log::info!(
+        "📂 Reprocessing {} as {} framebuffer",
+        path.display(),
+        model
+    );
+
+    if raw.len() != fb_format.window_bytes() {
+        log::warn!(
+            "⚠️ Raw dump is {} bytes but {} expects {}",
+            raw.len(),
+            model,
+            fb_format.window_bytes()
+        );
+    }
+
+    decode_framebuffer(&raw, &fb_format, opts)
+}
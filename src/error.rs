@@ -0,0 +1,174 @@
+use std::{fmt, path::PathBuf, time::Duration};
+
+/// Errors that can occur while capturing from a reMarkable tablet
+#[derive(Debug)]
+pub enum ResnapError {
+    /// A configuration value is out of range
+    InvalidConfig(String),
+    /// The tablet address is neither an IP address nor a valid hostname
+    InvalidHost(String),
+    /// The SSH connection or a remote command failed
+    Ssh(openssh::Error),
+    /// Connecting or a step on the tablet took longer than the configured timeout
+    Timeout(Duration),
+    /// The SSH identity file does not exist
+    IdentityNotFound(PathBuf),
+    /// The tablet model could not be determined from its machine name
+    UnknownModel(String),
+    /// No `xochitl` process is running on the tablet; holds the names of any other processes
+    /// mapping the framebuffer device
+    ProcessNotFound(Vec<String>),
+    /// No `xochitl` process maps the framebuffer device at this path
+    FramebufferMappingMissing(String),
+    /// Resolving a `.local` hostname with multicast DNS failed
+    Mdns(String),
+    /// Fewer framebuffer bytes than expected arrived, even after reading again
+    ShortRead { got: usize, expected: usize },
+    /// Reading the framebuffer over SFTP failed
+    Sftp(String),
+    /// The framebuffer address could not be read from `/proc/<pid>/maps`
+    InvalidAddress(String),
+    /// ffmpeg is not installed or not on `PATH`
+    FfmpegNotInstalled,
+    /// ffmpeg failed to convert the raw framebuffer
+    Ffmpeg(String),
+    /// The native decoder could not convert the raw framebuffer
+    Decode(String),
+    /// Copying to the system clipboard failed
+    Clipboard(String),
+    /// Running OCR on the image failed
+    Ocr(String),
+    /// Uploading to S3 failed
+    S3(String),
+    /// Sending a capture to the webhook failed
+    Webhook(String),
+    /// The webhook answered with a non-2xx status code
+    WebhookStatus(u16),
+    /// No significant handwritten content was found in the image
+    NoContent,
+    /// An output file already exists and overwriting wasn't allowed
+    OutputExists(PathBuf),
+    /// A local I/O operation failed
+    Io(std::io::Error),
+    /// Decoding or encoding an image failed
+    Image(image::ImageError),
+}
+
+impl fmt::Display for ResnapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResnapError::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            ResnapError::ShortRead { got, expected } => write!(
+                f,
+                "Framebuffer read was truncated: got {} of {} bytes",
+                got, expected
+            ),
+            ResnapError::Mdns(msg) => write!(f, "mDNS resolution failed: {}", msg),
+            ResnapError::InvalidHost(host) => write!(
+                f,
+                "Invalid tablet address {:?}: expected an IP address such as 10.11.99.1 or a \
+                 hostname such as remarkable.local",
+                host
+            ),
+            ResnapError::Ssh(e) => write!(f, "SSH error: {}", e),
+            ResnapError::Timeout(timeout) => {
+                write!(f, "Timed out after {}s", timeout.as_secs_f32())
+            }
+            ResnapError::IdentityNotFound(path) => {
+                write!(f, "SSH identity file not found: {}", path.display())
+            }
+            ResnapError::UnknownModel(machine) => {
+                write!(f, "Unknown reMarkable model: {:?}", machine)
+            }
+            ResnapError::ProcessNotFound(others) => {
+                write!(
+                    f,
+                    "xochitl is not running on the tablet; it may be asleep (try --wake) or \
+                     running a different UI app"
+                )?;
+                if !others.is_empty() {
+                    write!(f, " (the framebuffer is mapped by {})", others.join(", "))?;
+                }
+                Ok(())
+            }
+            ResnapError::FramebufferMappingMissing(device) => {
+                write!(
+                    f,
+                    "Could not find any xochitl process with {} mapping (check --fb)",
+                    device
+                )
+            }
+            ResnapError::Sftp(msg) => write!(
+                f,
+                "SFTP transfer failed: {} (the tablet may have no sftp-server; try --transfer dd)",
+                msg
+            ),
+            ResnapError::InvalidAddress(msg) => {
+                write!(f, "Could not find the framebuffer address: {}", msg)
+            }
+            ResnapError::FfmpegNotInstalled => write!(
+                f,
+                "ffmpeg was not found on PATH; install it from https://ffmpeg.org/download.html \
+                 or use the native decoder (--decoder native)"
+            ),
+            ResnapError::Ffmpeg(msg) => {
+                write!(f, "Failed to convert framebuffer to image: {}", msg)
+            }
+            ResnapError::Decode(msg) => write!(f, "Failed to decode framebuffer: {}", msg),
+            ResnapError::Clipboard(msg) => write!(f, "Failed to copy to clipboard: {}", msg),
+            ResnapError::Ocr(msg) => write!(f, "Failed to recognize text: {}", msg),
+            ResnapError::S3(msg) => write!(f, "Failed to upload to S3: {}", msg),
+            ResnapError::Webhook(msg) => write!(f, "Failed to post to webhook: {}", msg),
+            ResnapError::WebhookStatus(status) => {
+                write!(f, "Webhook responded with status {}", status)
+            }
+            ResnapError::NoContent => write!(f, "No significant content found in the image"),
+            ResnapError::OutputExists(path) => write!(
+                f,
+                "{} already exists; pass --force to overwrite it",
+                path.display()
+            ),
+            ResnapError::Io(e) => write!(f, "I/O error: {}", e),
+            ResnapError::Image(e) => write!(f, "Image error: {}", e),
+        }
+    }
+}
+
+impl ResnapError {
+    /// Whether the failure may go away by trying again, such as a dropped connection
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ResnapError::Ssh(_) | ResnapError::Timeout(_) | ResnapError::ShortRead { .. }
+        )
+    }
+}
+
+impl std::error::Error for ResnapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResnapError::Ssh(e) => Some(e),
+            ResnapError::Io(e) => Some(e),
+            ResnapError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<openssh::Error> for ResnapError {
+    fn from(e: openssh::Error) -> Self {
+        ResnapError::Ssh(e)
+    }
+}
+
+impl From<std::io::Error> for ResnapError {
+    fn from(e: std::io::Error) -> Self {
+        ResnapError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ResnapError {
+    fn from(e: image::ImageError) -> Self {
+        ResnapError::Image(e)
+    }
+}
@@ -0,0 +1,892 @@
+use crate::{FramebufferFormat, Model, Progress, RemoteExec, ResnapError, Stage};
+use flate2::read::GzDecoder;
+use openssh::{Session, Stdio};
+use openssh_sftp_client::{file::TokioCompatFile, Sftp, SftpOptions};
+use std::{
+    fmt,
+    io::{Read, SeekFrom},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+/// Ways of copying the framebuffer off the tablet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Transfer {
+    /// Read with `dd` in a remote shell
+    #[default]
+    Dd,
+    /// Read over SFTP, which needs an `sftp-server` on the tablet
+    Sftp,
+}
+
+/// Sources the screen can be captured from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Backend {
+    /// Use rm2fb when its shared framebuffer exists on a reMarkable 2, otherwise `mem`
+    #[default]
+    Auto,
+    /// Read xochitl's copy of the screen from `/proc/<pid>/mem`
+    Mem,
+    /// Read the shared-memory framebuffer of the rm2fb display server shim
+    Rm2fb,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::Auto => "auto",
+            Backend::Mem => "mem",
+            Backend::Rm2fb => "rm2fb",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Shared-memory framebuffer published by the rm2fb server
+pub const RM2FB_SHM_PATH: &str = "/dev/shm/swtfb.01";
+
+/// Where the framebuffer lives in the memory of the `xochitl` process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramebufferLocation {
+    /// ID of the `xochitl` process mapping the framebuffer device
+    pub pid: String,
+    /// Offset of the framebuffer in `/proc/<pid>/mem`
+    pub skip_bytes: u64,
+}
+
+/// Device details resolved by a capture and reused by later captures on the same session
+#[derive(Debug, Clone, Default)]
+pub struct CaptureCache {
+    pub(crate) model: Option<Model>,
+    pub(crate) location: Option<FramebufferLocation>,
+    pub(crate) backend: Option<Backend>,
+    pub(crate) fb_format: Option<FramebufferFormat>,
+}
+
+impl CaptureCache {
+    /// Forget the resolved details so the next capture looks them up again
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Remote command used by [`wake_device`] to unblank the display
+pub const WAKE_COMMAND: &str = "echo 0 > /sys/class/graphics/fb0/blank";
+
+/// How long to wait after waking for the framebuffer to refresh
+const WAKE_SETTLE: Duration = Duration::from_millis(500);
+
+/// Nudge the display awake so the framebuffer holds the current screen
+///
+/// Runs [`WAKE_COMMAND`], which writes `FB_BLANK_UNBLANK` to the framebuffer's `blank`
+/// attribute. It only turns the display on and doesn't touch notebooks or input devices.
+pub async fn wake_device(session: &impl RemoteExec) -> Result<(), ResnapError> {
+    log::info!("⏰ Waking display with: {}", WAKE_COMMAND);
+    let output = session.run(WAKE_COMMAND).await?;
+
+    if !output.status.success() {
+        log::warn!(
+            "⚠️ Wake command failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    tokio::time::sleep(WAKE_SETTLE).await;
+
+    Ok(())
+}
+
+/// Path of the framebuffer device with index `fb`, such as `/dev/fb0`
+pub(crate) fn framebuffer_device(fb: u32) -> String {
+    format!("/dev/fb{}", fb)
+}
+
+/// Find the `xochitl` process that maps the framebuffer `/dev/fb<fb>` and the framebuffer's
+/// address
+///
+/// `window_bytes` is the size of the framebuffer, used to pick among several mappings of the
+/// device.
+pub async fn locate_framebuffer(
+    session: &impl RemoteExec,
+    fb: u32,
+    window_bytes: usize,
+) -> Result<FramebufferLocation, ResnapError> {
+    let device = framebuffer_device(fb);
+
+    // Find `xochitl` process ID
+    let pid_output = session.run("/bin/pidof xochitl").await?.stdout;
+    let mut pid = match String::from_utf8_lossy(&pid_output)
+        .split_whitespace()
+        .next()
+    {
+        Some(pid) => pid.to_string(),
+        None => {
+            let others = framebuffer_processes(session, &device).await?;
+            return Err(ResnapError::ProcessNotFound(others));
+        }
+    };
+    log::info!("🆔 Found xochitl PID: {}", pid);
+
+    // Find framebuffer memory address
+    // First check if this process has the right mapping
+    let maps_check = session
+        .run(&format!("grep -w -C1 {} /proc/{}/maps", device, pid))
+        .await?;
+
+    if maps_check.stdout.is_empty() {
+        // If the first PID doesn't have the right mapping, find one that does
+        let pids_output = String::from_utf8_lossy(&pid_output);
+        let all_pids = pids_output.split_whitespace().collect::<Vec<&str>>();
+
+        let mut found_pid = None;
+        for test_pid in all_pids {
+            let check = session
+                .run(&format!("grep -w -C1 {} /proc/{}/maps", device, test_pid))
+                .await?;
+
+            if !check.stdout.is_empty() {
+                found_pid = Some(test_pid.to_string());
+                break;
+            }
+        }
+
+        if let Some(p) = found_pid {
+            log::info!("🔄 Switching to PID {} which has {} mapping", p, device);
+            pid.clear();
+            pid.push_str(&p);
+        } else {
+            return Err(ResnapError::FramebufferMappingMissing(device));
+        }
+    }
+
+    // Find the mapping after the framebuffer device mapping
+    let maps_output = session.run(&format!("cat /proc/{}/maps", pid)).await?;
+
+    let mapping_start = parse_framebuffer_mapping(
+        &String::from_utf8_lossy(&maps_output.stdout),
+        &device,
+        window_bytes,
+    )?;
+    let skip_bytes = mapping_start + FRAMEBUFFER_DATA_OFFSET;
+    log::info!(
+        "📍 Found framebuffer at address: 0x{:x} + {} = {}",
+        mapping_start,
+        FRAMEBUFFER_DATA_OFFSET,
+        skip_bytes
+    );
+
+    Ok(FramebufferLocation { pid, skip_bytes })
+}
+
+/// Whether the rm2fb shared-memory framebuffer exists on the tablet
+pub async fn has_rm2fb(session: &impl RemoteExec) -> Result<bool, ResnapError> {
+    let output = session.run(&format!("test -e {}", RM2FB_SHM_PATH)).await?;
+
+    Ok(output.status.success())
+}
+
+/// Remote command printing the name of every process that maps `device`
+fn framebuffer_processes_command(device: &str) -> String {
+    format!(
+        "for maps in $(grep -l -w {} /proc/[0-9]*/maps 2>/dev/null); do cat \"${{maps%/maps}}/comm\"; done",
+        device
+    )
+}
+
+/// Names of the processes mapping `device`, to explain a missing `xochitl`
+///
+/// Other launchers and reading apps replace `xochitl` on some setups.
+async fn framebuffer_processes(
+    session: &impl RemoteExec,
+    device: &str,
+) -> Result<Vec<String>, ResnapError> {
+    let output = session.run(&framebuffer_processes_command(device)).await?;
+
+    let mut names: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+/// Offset of the pixel data from the start of the mapping that follows the framebuffer device
+///
+/// xochitl keeps its copy of the screen in the anonymous mapping right after the `/dev/fb0`
+/// mapping, with the pixels starting 7 bytes in. The offset was found empirically and is the
+/// same one used by reStream.
+const FRAMEBUFFER_DATA_OFFSET: u64 = 7;
+
+/// Start address of the mapping holding the framebuffer, from the contents of
+/// `/proc/<pid>/maps`
+///
+/// Each mapping that directly follows a mapping of `device`, such as `/dev/fb0`, is a
+/// candidate. The smallest one
+/// large enough for `window_bytes` of pixels is picked, falling back to the last candidate
+/// when none is. Fails with [`ResnapError::InvalidAddress`] when there is no candidate whose
+/// address range is valid hex, so that a bad offset never gets dumped into an image.
+pub(crate) fn parse_framebuffer_mapping(
+    maps: &str,
+    device: &str,
+    window_bytes: usize,
+) -> Result<u64, ResnapError> {
+    let lines: Vec<&str> = maps.lines().collect();
+    let candidates: Vec<(u64, u64)> = lines
+        .windows(2)
+        .filter(|pair| pair[0].trim_end().ends_with(device))
+        .filter_map(|pair| {
+            let range = parse_mapping_range(pair[1]);
+            if range.is_none() {
+                log::warn!("⚠️ Skipping unparseable maps line {:?}", pair[1]);
+            }
+            range
+        })
+        .collect();
+
+    for (start, end) in &candidates {
+        log::debug!(
+            "🔎 Candidate framebuffer mapping 0x{:x}-0x{:x} ({} bytes)",
+            start,
+            end,
+            end - start
+        );
+    }
+
+    let needed = window_bytes as u64 + FRAMEBUFFER_DATA_OFFSET;
+    let fitting = candidates
+        .iter()
+        .filter(|(start, end)| end - start >= needed)
+        .min_by_key(|(start, end)| end - start);
+
+    match fitting.or(candidates.last()) {
+        Some((start, end)) => {
+            if fitting.is_none() {
+                log::warn!(
+                    "⚠️ No mapping after {} is large enough for {} bytes, using the last one",
+                    device,
+                    window_bytes
+                );
+            } else if candidates.len() > 1 {
+                log::info!(
+                    "🔎 Picked mapping 0x{:x}-0x{:x} out of {} candidates",
+                    start,
+                    end,
+                    candidates.len()
+                );
+            }
+            Ok(*start)
+        }
+        None => Err(ResnapError::InvalidAddress(format!(
+            "no valid mapping follows {} in /proc/<pid>/maps ({} lines mention it)",
+            device,
+            lines
+                .iter()
+                .filter(|line| line.trim_end().ends_with(device))
+                .count()
+        ))),
+    }
+}
+
+/// Parse the `start-end` address range at the start of a `/proc/<pid>/maps` line
+fn parse_mapping_range(line: &str) -> Option<(u64, u64)> {
+    let range = line.split_whitespace().next()?;
+    let (start, end) = range.split_once('-')?;
+    let is_hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_hex(start) || !is_hex(end) {
+        return None;
+    }
+
+    let start = u64::from_str_radix(start, 16).ok()?;
+    let end = u64::from_str_radix(end, 16).ok()?;
+    (start + FRAMEBUFFER_DATA_OFFSET < end).then_some((start, end))
+}
+
+/// Smallest block read with `--chunk-size`, since each block costs a round trip to the tablet
+pub const MIN_CHUNK_SIZE: usize = 4096;
+
+/// Shell command copying `window_bytes` from `source`, starting `skip_bytes` in
+///
+/// `iflag=skip_bytes` lets one dd seek straight to the byte offset; dd builds without it reject
+/// the flag before reading anything, so they fall back to seeking with a separate zero-length
+/// dd. With `compress` the output is piped through `gzip -1`, which the tablet must have.
+fn dd_command(source: &str, skip_bytes: u64, window_bytes: usize, compress: bool) -> String {
+    let dd_cmd = format!(
+        "{{ dd if={source} bs={window} skip={skip} count=1 iflag=skip_bytes 2>/dev/null \
+         || {{ dd bs=1 skip={skip} count=0 && dd bs={window} count=1; }} < {source} 2>/dev/null; }}",
+        source = source,
+        skip = skip_bytes,
+        window = window_bytes,
+    );
+    if compress {
+        format!("{} | gzip -1", dd_cmd)
+    } else {
+        dd_cmd
+    }
+}
+
+/// Whether `gzip` can be run on the tablet
+async fn has_gzip(session: &Session) -> Result<bool, ResnapError> {
+    let status = session
+        .command("sh")
+        .arg("-c")
+        .arg("command -v gzip >/dev/null 2>&1")
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+/// Offsets and lengths of the pieces `window_bytes` starting at `offset` is read in, each at
+/// most `chunk_size` bytes but no less than [`MIN_CHUNK_SIZE`]; the whole window in one piece
+/// without a chunk size
+fn chunk_ranges(offset: u64, window_bytes: usize, chunk_size: Option<usize>) -> Vec<(u64, usize)> {
+    let chunk = match chunk_size.map(|chunk| chunk.max(MIN_CHUNK_SIZE)) {
+        Some(chunk) if chunk < window_bytes => chunk,
+        _ => window_bytes.max(1),
+    };
+    (0..window_bytes)
+        .step_by(chunk)
+        .map(|start| (offset + start as u64, chunk.min(window_bytes - start)))
+        .collect()
+}
+
+/// Read `window_bytes` of framebuffer data from `path`, starting `offset` bytes in, with `dd`
+///
+/// Returns whatever `dd` produced, which is empty if the process has gone away. The data is
+/// streamed so that `progress` sees [`Stage::Transferring`] as it arrives. With `chunk_size`,
+/// each block of that many bytes is read by its own `dd` and the blocks are joined here; a
+/// block that comes back short is read again once, and the data stops at a block that is still
+/// short. With `compress` the data is gzipped on the tablet when `gzip` is available there and
+/// inflated here. The time the transfer took and the bytes that crossed the connection are
+/// logged, to compare with and without `compress`.
+pub(crate) async fn dump_framebuffer(
+    session: &Session,
+    path: &str,
+    offset: u64,
+    window_bytes: usize,
+    chunk_size: Option<usize>,
+    compress: bool,
+    progress: &Progress<'_>,
+) -> Result<Vec<u8>, ResnapError> {
+    let chunks = chunk_ranges(offset, window_bytes, chunk_size);
+
+    log::info!("📤 Extracting framebuffer data from {}...", path);
+    if chunks.len() > 1 {
+        log::info!(
+            "🧩 Reading {} chunks of up to {} bytes",
+            chunks.len(),
+            chunks[0].1
+        );
+    }
+
+    let gzip = compress && has_gzip(session).await?;
+    if compress && !gzip {
+        log::warn!("⚠️ gzip not found on the tablet, transferring uncompressed");
+    }
+
+    let start = Instant::now();
+    let mut fb_data = Vec::with_capacity(window_bytes);
+    let mut transferred = 0;
+    for (chunk_offset, chunk_len) in chunks.iter().copied() {
+        // Count the bytes of earlier chunks towards the transfer
+        let done = fb_data.len();
+        let chunk_progress = |stage: Stage| match stage {
+            Stage::Transferring { read, .. } => progress(Stage::Transferring {
+                read: done + read,
+                total: window_bytes,
+            }),
+            stage => progress(stage),
+        };
+
+        let read = || {
+            dd_chunk(
+                session,
+                path,
+                chunk_offset,
+                chunk_len,
+                gzip,
+                &chunk_progress,
+            )
+        };
+        let (mut chunk, mut sent) = read().await?;
+        // A short window is read again by the caller, so only chunks are retried here
+        if chunk.len() != chunk_len && chunks.len() > 1 {
+            log::warn!(
+                "⚠️ Read {} of {} bytes at offset {}, reading the chunk again",
+                chunk.len(),
+                chunk_len,
+                chunk_offset
+            );
+            transferred += sent;
+            (chunk, sent) = read().await?;
+        }
+        transferred += sent;
+
+        fb_data.extend_from_slice(&chunk);
+        if chunk.len() < chunk_len {
+            // Later chunks can't be joined on after a gap
+            break;
+        }
+    }
+
+    if gzip {
+        log::info!(
+            "🗜️ Transferred {} bytes in {:.2?}, inflated to {}",
+            transferred,
+            start.elapsed(),
+            fb_data.len()
+        );
+    } else {
+        log::info!(
+            "📥 Transferred {} bytes in {:.2?}",
+            transferred,
+            start.elapsed()
+        );
+    }
+
+    Ok(fb_data)
+}
+
+/// Read `len` bytes from `path`, starting `offset` bytes in, with one `dd` on the tablet
+///
+/// Returns the data, inflated when `compress` gzipped it on the tablet, and the number of bytes
+/// that were transferred.
+async fn dd_chunk(
+    session: &Session,
+    path: &str,
+    offset: u64,
+    len: usize,
+    compress: bool,
+    progress: &Progress<'_>,
+) -> Result<(Vec<u8>, usize), ResnapError> {
+    let mut child = session
+        .command("sh")
+        .arg("-c")
+        .arg(dd_command(path, offset, len, compress))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .await?;
+    let stdout = child.stdout().take().expect("stdout is piped");
+    let data = read_with_progress(stdout, len, progress).await?;
+
+    // A short read is detected by the caller, so the exit status isn't checked
+    child.wait().await?;
+
+    if compress {
+        let mut inflated = Vec::with_capacity(len);
+        GzDecoder::new(data.as_slice()).read_to_end(&mut inflated)?;
+        progress(Stage::Transferring {
+            read: inflated.len(),
+            total: len,
+        });
+        Ok((inflated, data.len()))
+    } else {
+        let sent = data.len();
+        Ok((data, sent))
+    }
+}
+
+/// Read `window_bytes` of framebuffer data from `path`, starting `offset` bytes in, over SFTP
+///
+/// Seeks straight to the framebuffer instead of skipping to it byte by byte like `dd`, and
+/// runs no shell. Most tablets run dropbear, which has no `sftp-server` unless one is
+/// installed, in which case this fails with [`ResnapError::Sftp`].
+pub(crate) async fn read_framebuffer_sftp(
+    session: &Session,
+    path: &str,
+    offset: u64,
+    window_bytes: usize,
+    progress: &Progress<'_>,
+) -> Result<Vec<u8>, ResnapError> {
+    let sftp_error = |e: openssh_sftp_client::Error| ResnapError::Sftp(e.to_string());
+
+    log::info!("📤 Extracting framebuffer data from {} over SFTP...", path);
+    let mut child = session
+        .subsystem("sftp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .await?;
+    let stdin = child.stdin().take().expect("stdin is piped");
+    let stdout = child.stdout().take().expect("stdout is piped");
+
+    let sftp = Sftp::new(stdin, stdout, SftpOptions::default())
+        .await
+        .map_err(sftp_error)?;
+    let file = sftp.open(path).await.map_err(sftp_error)?;
+
+    let mut file = Box::pin(TokioCompatFile::new(file));
+    file.seek(SeekFrom::Start(offset)).await?;
+    let fb_data =
+        read_with_progress(file.take(window_bytes as u64), window_bytes, progress).await?;
+
+    sftp.close().await.map_err(sftp_error)?;
+    child.wait().await?;
+
+    Ok(fb_data)
+}
+
+/// Read a stream to its end, reporting [`Stage::Transferring`] for each chunk
+pub(crate) async fn read_with_progress(
+    mut reader: impl AsyncRead + Unpin,
+    window_bytes: usize,
+    progress: &Progress<'_>,
+) -> Result<Vec<u8>, ResnapError> {
+    let mut fb_data = Vec::with_capacity(window_bytes);
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        fb_data.extend_from_slice(&chunk[..read]);
+        progress(Stage::Transferring {
+            read: fb_data.len(),
+            total: window_bytes,
+        });
+    }
+
+    Ok(fb_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{capture_once_cached, CaptureConfigBuilder, Decoder, Transfer};
+    use std::{
+        collections::HashMap,
+        fs, io,
+        path::PathBuf,
+        process::{Command, ExitStatus, Output},
+    };
+
+    /// Size of the reMarkable 2 framebuffer
+    const RM2_WINDOW: usize = 1872 * 1404 * 2;
+
+    /// `/proc/<pid>/maps` of an xochitl process with a small fb0 mapping followed by the
+    /// anonymous mapping holding the screen
+    const XOCHITL_MAPS: &str = "\
+00010000-00400000 r-xp 00000000 b3:02 1234       /usr/bin/xochitl
+00001000-00002000 rw-s 00000000 00:06 185        /dev/fb0
+00002000-00506000 rw-p 00000000 00:00 0
+7e900000-7e921000 rw-p 00000000 00:00 0          [stack]
+";
+
+    /// A tablet answering the commands sent during a capture from canned output
+    ///
+    /// Commands it doesn't know, such as reading sysfs, print nothing.
+    #[derive(Default)]
+    struct FakeTablet {
+        /// Output of `pidof xochitl`
+        pids: &'static str,
+        /// `/proc/<pid>/maps` of each process
+        maps: HashMap<&'static str, &'static str>,
+        /// Memory of every process
+        mem: Vec<u8>,
+    }
+
+    impl RemoteExec for FakeTablet {
+        async fn run(&self, command: &str) -> Result<Output, ResnapError> {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            let maps_of = |path: &str| {
+                let pid = path.trim_start_matches("/proc/").trim_end_matches("/maps");
+                self.maps.get(pid).copied().unwrap_or_default()
+            };
+
+            let output = match words.as_slice() {
+                ["/bin/pidof", "xochitl"] => self.pids.to_string(),
+                ["grep", "-w", "-C1", device, path] => {
+                    let maps = maps_of(path);
+                    if maps.lines().any(|line| line.ends_with(device)) {
+                        maps.to_string()
+                    } else {
+                        String::new()
+                    }
+                }
+                ["cat", path] => maps_of(path).to_string(),
+                // Listing the other processes mapping the framebuffer
+                _ if command.starts_with("for maps in") => "koreader\n".to_string(),
+                _ => String::new(),
+            };
+
+            Ok(Output {
+                status: ExitStatus::default(),
+                stdout: output.into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+
+        async fn read_mem(
+            &self,
+            path: &str,
+            offset: u64,
+            len: usize,
+            _transfer: Transfer,
+            _chunk_size: Option<usize>,
+            _compress: bool,
+            _progress: &Progress<'_>,
+        ) -> Result<Vec<u8>, ResnapError> {
+            assert!(path.ends_with("/mem"), "unexpected read of {}", path);
+            let pid = path.trim_start_matches("/proc/").trim_end_matches("/mem");
+            if !self.maps.contains_key(pid) {
+                // The process is gone, as after xochitl restarts
+                return Err(ResnapError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: No such file or directory", path),
+                )));
+            }
+            let start = (offset as usize).min(self.mem.len());
+            let end = (start + len).min(self.mem.len());
+            Ok(self.mem[start..end].to_vec())
+        }
+    }
+
+    /// A temporary file that is removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("resnap-{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// A blank reMarkable 2 page with a filled 100x100 stroke at raw columns 800-899 and rows
+    /// 600-699, in xochitl's `gray16` range
+    fn rm2_page() -> Vec<u8> {
+        (0..1404)
+            .flat_map(|y| (0..1872).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let stroke = (800..900).contains(&x) && (600..700).contains(&y);
+                let level: u16 = if stroke { 0 } else { 4000 };
+                level.to_le_bytes()
+            })
+            .collect()
+    }
+
+    /// Run a command the way the tablet's shell would, returning its standard output
+    fn run_locally(command: &str) -> Vec<u8> {
+        let output = Command::new("sh").arg("-c").arg(command).output().unwrap();
+        assert!(output.status.success(), "{:?} failed", command);
+        output.stdout
+    }
+
+    #[test]
+    fn picks_the_smallest_mapping_that_fits() {
+        let maps = "\
+00001000-00002000 rw-s 00000000 00:06 185 /dev/fb0
+00002000-00003000 rw-p 00000000 00:00 0
+00003000-00004000 rw-s 00000000 00:06 185 /dev/fb0
+10000000-20000000 rw-p 00000000 00:00 0
+00005000-00006000 rw-s 00000000 00:06 185 /dev/fb0
+00100000-00700000 rw-p 00000000 00:00 0
+";
+        let start = parse_framebuffer_mapping(maps, "/dev/fb0", RM2_WINDOW).unwrap();
+        assert_eq!(start, 0x100000);
+    }
+
+    #[test]
+    fn ignores_other_framebuffer_devices() {
+        let maps = "\
+00001000-00002000 rw-s 00000000 00:06 185 /dev/fb10
+00002000-00506000 rw-p 00000000 00:00 0
+";
+        assert!(matches!(
+            parse_framebuffer_mapping(maps, "/dev/fb1", RM2_WINDOW),
+            Err(ResnapError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unparseable_addresses() {
+        let maps = "\
+00001000-00002000 rw-s 00000000 00:06 185 /dev/fb0
+zzzz-00506000 rw-p 00000000 00:00 0
+";
+        assert!(matches!(
+            parse_framebuffer_mapping(maps, "/dev/fb0", RM2_WINDOW),
+            Err(ResnapError::InvalidAddress(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn switches_to_the_pid_mapping_the_framebuffer() {
+        let tablet = FakeTablet {
+            pids: "101 202\n",
+            maps: HashMap::from([("101", ""), ("202", XOCHITL_MAPS)]),
+            ..FakeTablet::default()
+        };
+
+        let location = locate_framebuffer(&tablet, 0, RM2_WINDOW).await.unwrap();
+        assert_eq!(location.pid, "202");
+        assert_eq!(location.skip_bytes, 0x2000 + FRAMEBUFFER_DATA_OFFSET);
+    }
+
+    #[tokio::test]
+    async fn reports_a_missing_mapping_for_the_requested_device() {
+        let tablet = FakeTablet {
+            pids: "101\n",
+            maps: HashMap::from([("101", XOCHITL_MAPS)]),
+            ..FakeTablet::default()
+        };
+
+        let err = locate_framebuffer(&tablet, 1, RM2_WINDOW)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, ResnapError::FramebufferMappingMissing(device) if device == "/dev/fb1")
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_other_processes_when_xochitl_is_missing() {
+        let tablet = FakeTablet::default();
+
+        let err = locate_framebuffer(&tablet, 0, RM2_WINDOW)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ResnapError::ProcessNotFound(others) if others == ["koreader"]));
+    }
+
+    #[test]
+    fn dd_command_copies_the_window_after_the_offset() {
+        let mem: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let file = TempFile::new("dd-mem", &mem);
+        let source = file.0.display().to_string();
+
+        let data = run_locally(&dd_command(&source, 1234, 4096, false));
+        assert_eq!(data, &mem[1234..1234 + 4096]);
+    }
+
+    #[test]
+    fn dd_command_gzips_the_window() {
+        let mem: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let file = TempFile::new("dd-gzip", &mem);
+        let source = file.0.display().to_string();
+
+        let data = run_locally(&dd_command(&source, 1234, 4096, true));
+        let mut inflated = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, &mem[1234..1234 + 4096]);
+    }
+
+    #[test]
+    fn chunks_cover_the_window() {
+        assert_eq!(chunk_ranges(1234, 10_000, None), [(1234, 10_000)]);
+        assert_eq!(chunk_ranges(1234, 10_000, Some(16_384)), [(1234, 10_000)]);
+        assert_eq!(
+            chunk_ranges(1234, 10_000, Some(4096)),
+            [(1234, 4096), (5330, 4096), (9426, 1808)]
+        );
+
+        // Tiny chunks are raised to the minimum rather than read a few bytes at a time
+        assert_eq!(
+            chunk_ranges(1234, 10_000, Some(1)),
+            chunk_ranges(1234, 10_000, Some(MIN_CHUNK_SIZE))
+        );
+    }
+
+    #[test]
+    fn dd_command_chunks_join_into_the_window() {
+        let mem: Vec<u8> = (0..=255).cycle().take(20_000).collect();
+        let file = TempFile::new("dd-chunks", &mem);
+        let source = file.0.display().to_string();
+
+        let data: Vec<u8> = chunk_ranges(1234, 10_000, Some(4096))
+            .into_iter()
+            .flat_map(|(offset, len)| run_locally(&dd_command(&source, offset, len, false)))
+            .collect();
+        assert_eq!(data, &mem[1234..1234 + 10_000]);
+    }
+
+    #[test]
+    fn dd_command_falls_back_without_skip_bytes() {
+        let mem: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let file = TempFile::new("dd-fallback", &mem);
+        let source = file.0.display().to_string();
+
+        // Only the fallback branch, as run by a dd without `iflag=skip_bytes`
+        let command = dd_command(&source, 1234, 4096, false);
+        let fallback = command.split("|| ").nth(1).unwrap().trim_end_matches("; }");
+        assert_eq!(run_locally(fallback), &mem[1234..1234 + 4096]);
+    }
+
+    #[tokio::test]
+    async fn captures_a_fake_tablet_end_to_end() {
+        // Process memory with the page where the maps say it is
+        let mut mem = vec![0xaa; 0x2000 + FRAMEBUFFER_DATA_OFFSET as usize];
+        mem.extend(rm2_page());
+        let tablet = FakeTablet {
+            pids: "202\n",
+            maps: HashMap::from([("202", XOCHITL_MAPS)]),
+            mem,
+        };
+        let config = CaptureConfigBuilder::new("remarkable")
+            .model(Model::Rm2)
+            .backend(Backend::Mem)
+            .decoder(Decoder::Native)
+            .build()
+            .unwrap();
+
+        let mut cache = CaptureCache::default();
+        let capture = capture_once_cached(&tablet, &config, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(cache.location.unwrap().pid, "202");
+        assert_eq!(
+            (capture.screen.width(), capture.screen.height()),
+            (1404, 1872)
+        );
+
+        // The stroke lands at columns 704-803 and rows 972-1071 once oriented
+        let rect = capture.detection.unwrap().bounding_box.unwrap();
+        assert!(rect.x <= 704 && rect.x + rect.width >= 804);
+        assert!(rect.y <= 972 && rect.y + rect.height >= 1072);
+        assert!(rect.width < 400 && rect.height < 400);
+        assert!(capture.cropped.is_some());
+    }
+
+    #[tokio::test]
+    async fn locates_the_framebuffer_again_when_the_cached_pid_is_gone() {
+        let mut mem = vec![0xaa; 0x2000 + FRAMEBUFFER_DATA_OFFSET as usize];
+        mem.extend(rm2_page());
+        let tablet = FakeTablet {
+            pids: "202\n",
+            maps: HashMap::from([("202", XOCHITL_MAPS)]),
+            mem,
+        };
+        let config = CaptureConfigBuilder::new("remarkable")
+            .model(Model::Rm2)
+            .backend(Backend::Mem)
+            .decoder(Decoder::Native)
+            .build()
+            .unwrap();
+
+        // Left over from an xochitl that has since restarted as PID 202
+        let mut cache = CaptureCache {
+            location: Some(FramebufferLocation {
+                pid: "101".to_string(),
+                skip_bytes: 0x2000,
+            }),
+            ..CaptureCache::default()
+        };
+        capture_once_cached(&tablet, &config, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(cache.location.unwrap().pid, "202");
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    detect_framebuffer_format, locate_framebuffer, metadata::json_string, resolve_model,
+    with_timeout, CaptureConfig, FramebufferFormat, FramebufferLocation, Model, RemoteExec,
+    ResnapError,
+};
+use std::fmt::Write;
+
+/// Reads the charge of the first battery reported by the kernel, in percent
+const BATTERY_COMMAND: &str = "cat /sys/class/power_supply/*/capacity 2>/dev/null | head -n 1";
+
+/// Reads the software release, from the updater config on older firmware and `os-release` on
+/// newer firmware
+const FIRMWARE_COMMAND: &str = "grep -h -s -e '^REMARKABLE_RELEASE_VERSION=' -e '^IMG_VERSION=' \
+     /usr/share/remarkable/update.conf /etc/os-release | head -n 1 | cut -d= -f2 | tr -d '\"'";
+
+/// Facts about a tablet, gathered without capturing its screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    /// Model of the tablet
+    pub model: Model,
+    /// Index of the framebuffer device `/dev/fb<n>` that was checked
+    pub fb: u32,
+    /// Layout of the framebuffer, including its resolution
+    pub framebuffer: FramebufferFormat,
+    /// Software release installed on the tablet; `None` when it couldn't be read
+    pub firmware: Option<String>,
+    /// Battery charge in percent; `None` when the tablet doesn't report it
+    pub battery: Option<u8>,
+    /// Where `xochitl` maps the framebuffer, or why it couldn't be found
+    pub location: Result<FramebufferLocation, String>,
+}
+
+impl DeviceInfo {
+    /// Serialize the information as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        let _ = write!(json, "\"model\":{}", json_string(&self.model.to_string()));
+        let _ = write!(
+            json,
+            ",\"firmware\":{}",
+            self.firmware
+                .as_deref()
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(json, ",\"width\":{}", self.framebuffer.width);
+        let _ = write!(json, ",\"height\":{}", self.framebuffer.height);
+        let _ = write!(
+            json,
+            ",\"pixel_format\":{}",
+            json_string(self.framebuffer.pixel_format)
+        );
+        let _ = write!(
+            json,
+            ",\"bytes_per_pixel\":{}",
+            self.framebuffer.bytes_per_pixel
+        );
+        let _ = write!(json, ",\"dpi\":{}", self.model.dpi());
+        let _ = write!(
+            json,
+            ",\"battery\":{}",
+            self.battery
+                .map(|battery| battery.to_string())
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(json, ",\"fb\":{}", self.fb);
+        let _ = write!(json, ",\"fb_mapping\":{}", self.location.is_ok());
+        let _ = write!(
+            json,
+            ",\"pid\":{}",
+            self.location
+                .as_ref()
+                .map(|location| json_string(&location.pid))
+                .unwrap_or_else(|_| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"fb_error\":{}",
+            self.location
+                .as_ref()
+                .err()
+                .map(|e| json_string(e))
+                .unwrap_or_else(|| "null".to_string())
+        );
+        json.push('}');
+        json
+    }
+}
+
+/// Read the tablet's battery charge in percent; `None` when the tablet doesn't report it
+pub async fn read_battery(session: &impl RemoteExec) -> Result<Option<u8>, ResnapError> {
+    let output = session.run(BATTERY_COMMAND).await?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+}
+
+/// Read the tablet's model, firmware, framebuffer layout and battery charge over an open
+/// session, and check that the framebuffer mapping can be found
+///
+/// A missing mapping is reported in [`DeviceInfo::location`] rather than as an error, so the
+/// other details are still available for diagnosis.
+pub async fn device_info(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+) -> Result<DeviceInfo, ResnapError> {
+    let model = resolve_model(session, config).await?;
+    let framebuffer = with_timeout(
+        config.timeout,
+        detect_framebuffer_format(session, model, config.framebuffer),
+    )
+    .await??;
+
+    let battery = with_timeout(config.timeout, read_battery(session)).await??;
+
+    let output = with_timeout(config.timeout, session.run(FIRMWARE_COMMAND)).await??;
+    let firmware = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let location = with_timeout(
+        config.timeout,
+        locate_framebuffer(session, config.framebuffer, framebuffer.window_bytes()),
+    )
+    .await?
+    .map_err(|e| e.to_string());
+
+    Ok(DeviceInfo {
+        model,
+        fb: config.framebuffer,
+        framebuffer,
+        firmware: (!firmware.is_empty()).then_some(firmware),
+        battery,
+        location,
+    })
+}
@@ -0,0 +1,893 @@
+use image::DynamicImage;
+use openssh::{Session, SessionBuilder};
+use std::{
+    fs,
+    future::Future,
+    net::{IpAddr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+mod animation;
+mod builder;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod config_file;
+mod crop;
+mod decode;
+mod error;
+mod framebuffer;
+mod info;
+mod logging;
+mod manifest;
+#[cfg(feature = "mdns")]
+mod mdns;
+mod metadata;
+mod model;
+#[cfg(feature = "ocr")]
+mod ocr;
+mod output;
+mod pdf;
+mod progress;
+mod remote;
+mod retry;
+#[cfg(feature = "s3")]
+mod s3;
+mod svg;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+use crop::trim_rect;
+use progress::no_progress;
+
+pub use animation::GifAnimation;
+pub use builder::CaptureConfigBuilder;
+#[cfg(feature = "clipboard")]
+pub use clipboard::copy_to_clipboard;
+pub use config_file::{config_file_args, find_config_file, CONFIG_FILE_NAME};
+pub use crop::{
+    content_bounding_box, crop_to_content, crop_to_rect, detect_content, split_regions,
+    ContentDetection, CropOptions, Rect, ThresholdMode, UiMask,
+};
+pub use decode::{
+    apply_curve, decode_framebuffer, decode_raw_file, DecodeOptions, Decoder, Rotation,
+};
+pub use error::ResnapError;
+pub use framebuffer::{
+    has_rm2fb, locate_framebuffer, wake_device, Backend, CaptureCache, FramebufferLocation,
+    Transfer, MIN_CHUNK_SIZE, RM2FB_SHM_PATH, WAKE_COMMAND,
+};
+pub use info::{device_info, read_battery, DeviceInfo};
+pub use logging::{json_log_record, LogFormat};
+pub use manifest::{append_to_manifest, FileDigest};
+#[cfg(feature = "mdns")]
+pub use mdns::{is_mdns_host, resolve_mdns, MDNS_TIMEOUT};
+pub use metadata::CaptureMetadata;
+pub use model::{detect_framebuffer_format, detect_model, FramebufferFormat, Model};
+#[cfg(feature = "ocr")]
+pub use ocr::{recognize_text, DEFAULT_OCR_LANG};
+pub use openssh::KnownHosts;
+pub use output::{
+    save_image, write_image, NameTemplate, OutputFormat, OutputOptions, PngCompression,
+};
+pub use pdf::PdfDocument;
+pub use progress::{Progress, Stage, StageTimings};
+pub use remote::{LocalExec, RemoteExec};
+pub use retry::RetryPolicy;
+#[cfg(feature = "s3")]
+pub use s3::{upload_to_s3, S3Target};
+pub use svg::{contours_to_svg, SvgOptions};
+#[cfg(feature = "webhook")]
+pub use webhook::{post_to_webhook, WebhookOptions};
+
+/// Settings for a single capture from a reMarkable tablet
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// IP address of the reMarkable tablet, or a host alias from the SSH config
+    pub ip_address: String,
+
+    /// SSH login user on the tablet; `None` takes the `User` from the SSH config, or `root`
+    pub user: Option<String>,
+
+    /// SSH port of the tablet; `None` takes the `Port` from the SSH config, or 22
+    pub port: Option<u16>,
+
+    /// SSH config file to read instead of `~/.ssh/config`
+    pub ssh_config: Option<PathBuf>,
+
+    /// How the tablet's host key is checked against the known hosts file
+    pub known_hosts: KnownHosts,
+
+    /// Time limit for connecting and for each step run on the tablet; `None` waits forever
+    pub timeout: Option<Duration>,
+
+    /// Retries of connection failures and timeouts
+    pub retry: RetryPolicy,
+
+    /// SSH private key used instead of the default identities
+    pub identity: Option<PathBuf>,
+
+    /// Jump host `[user@]host[:port]` to tunnel the connection through, as with `ssh -J`;
+    /// several can be chained with commas
+    pub jump: Option<String>,
+
+    /// Tablet model, which determines the framebuffer layout; detected over SSH when `None`
+    pub model: Option<Model>,
+
+    /// Wake the display before reading the framebuffer
+    pub wake: bool,
+
+    /// Where the screen is read from on the tablet
+    pub backend: Backend,
+
+    /// Index `n` of the framebuffer device `/dev/fb<n>` to capture; only used with
+    /// [`Backend::Mem`]
+    pub framebuffer: u32,
+
+    /// How the framebuffer is copied off the tablet; only used with [`Backend::Mem`]
+    pub transfer: Transfer,
+
+    /// Read the framebuffer with a separate `dd` for each block of this many bytes instead of
+    /// all at once, for firmware that truncates one big read; only used with [`Transfer::Dd`]
+    pub chunk_size: Option<usize>,
+
+    /// Compress the framebuffer with `gzip` on the tablet before transferring it; only used
+    /// with [`Transfer::Dd`]
+    pub compress: bool,
+
+    /// Options for converting the raw framebuffer into an image
+    pub decode: DecodeOptions,
+
+    /// Options for detecting and cropping the handwritten content; `None` keeps the full screen
+    pub crop: Option<CropOptions>,
+
+    /// File to keep the raw framebuffer dump in, if any
+    pub keep_raw: Option<PathBuf>,
+}
+
+impl CaptureConfig {
+    /// Start building a configuration for the tablet at `ip_address` with the CLI defaults
+    pub fn builder(ip_address: impl Into<String>) -> CaptureConfigBuilder {
+        CaptureConfigBuilder::new(ip_address)
+    }
+}
+
+/// Images produced by a single capture
+#[derive(Debug, Clone)]
+pub struct Capture {
+    /// Model of the tablet the screen was captured from
+    pub model: Model,
+
+    /// Full oriented and contrast-adjusted screen image
+    pub screen: DynamicImage,
+
+    /// Result of the content detection; `None` when cropping is disabled
+    pub detection: Option<ContentDetection>,
+
+    /// Cropped handwritten content, or the fixed crop; `None` when cropping is disabled or
+    /// nothing was found
+    pub cropped: Option<DynamicImage>,
+
+    /// Rectangle of `screen` that `cropped` was taken from, after any trimming
+    pub crop_rect: Option<Rect>,
+
+    /// Battery charge of the tablet in percent; `None` when it couldn't be read or the screen
+    /// didn't come from a tablet
+    pub battery: Option<u8>,
+}
+
+impl Capture {
+    /// Build a capture from a screen image, cropping it when options are given
+    ///
+    /// Fails with [`ResnapError::InvalidConfig`] when [`CropOptions::region`] or
+    /// [`CropOptions::fixed_rect`] doesn't lie within the screen.
+    pub fn from_screen(
+        screen: DynamicImage,
+        model: Model,
+        crop: Option<&CropOptions>,
+    ) -> Result<Self, ResnapError> {
+        Self::from_screen_with_progress(screen, model, crop, &no_progress)
+    }
+
+    /// Build a capture from a screen image, reporting the detection and cropping stages
+    fn from_screen_with_progress(
+        screen: DynamicImage,
+        model: Model,
+        crop: Option<&CropOptions>,
+        progress: &Progress<'_>,
+    ) -> Result<Self, ResnapError> {
+        if let Some(rect) = crop.and_then(|opts| opts.region.as_ref()) {
+            check_fits_screen("region", rect, &screen)?;
+        }
+        if let Some(rect) = crop.and_then(|opts| opts.fixed_rect.as_ref()) {
+            check_fits_screen("fixed crop", rect, &screen)?;
+        }
+
+        let detection = match crop {
+            // A region is taken as the content as it is, without searching the screen
+            Some(CropOptions {
+                region: Some(rect), ..
+            }) => Some(ContentDetection {
+                contours_found: 0,
+                contours_significant: 0,
+                bounding_box: Some(*rect),
+                contours: Vec::new(),
+                contour_vis: None,
+            }),
+            Some(opts) => {
+                progress(Stage::DetectingContours);
+                Some(detect_content(&screen, opts))
+            }
+            None => None,
+        };
+        let crop_rect = crop.zip(detection.as_ref()).and_then(|(opts, detection)| {
+            let rect = opts.fixed_rect.or(detection.bounding_box)?;
+            progress(Stage::Cropping);
+            // The fixed crop isn't trimmed, so that every capture has its size
+            Some(match (opts.fixed_rect, opts.trim) {
+                (None, Some(tolerance)) => trim_rect(&screen, rect, tolerance, opts.invert),
+                _ => rect,
+            })
+        });
+        let cropped = crop.zip(crop_rect).map(|(opts, rect)| {
+            let opts = CropOptions {
+                trim: None,
+                ..opts.clone()
+            };
+            crop_to_rect(&screen, rect, &opts)
+        });
+
+        Ok(Self {
+            model,
+            screen,
+            detection,
+            cropped,
+            crop_rect,
+            battery: None,
+        })
+    }
+}
+
+/// Fail with [`ResnapError::InvalidConfig`] unless `rect`, named `name` in the error, lies
+/// within `screen`
+fn check_fits_screen(name: &str, rect: &Rect, screen: &DynamicImage) -> Result<(), ResnapError> {
+    let (width, height) = (screen.width(), screen.height());
+    if rect.x.saturating_add(rect.width) > width || rect.y.saturating_add(rect.height) > height {
+        return Err(ResnapError::InvalidConfig(format!(
+            "{} {},{},{},{} doesn't fit the {}x{} screen",
+            name, rect.x, rect.y, rect.width, rect.height, width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Connect to the tablet, capture the screen and return the cropped handwritten content
+///
+/// The full screen image is returned when cropping is disabled.
+pub async fn capture(config: &CaptureConfig) -> Result<DynamicImage, ResnapError> {
+    capture_with_progress(config, &no_progress).await
+}
+
+/// Like [`capture`], calling `progress` as each [`Stage`] of the capture is reached
+///
+/// Stages are reported again from [`Stage::Connecting`] when a failed attempt is retried.
+pub async fn capture_with_progress(
+    config: &CaptureConfig,
+    progress: &Progress<'_>,
+) -> Result<DynamicImage, ResnapError> {
+    let capture = config
+        .retry
+        .run(|| connect_and_capture_with_progress(config, progress))
+        .await?;
+
+    match config.crop {
+        Some(_) => capture.cropped.ok_or(ResnapError::NoContent),
+        None => Ok(capture.screen),
+    }
+}
+
+/// Open a session, capture and crop the screen once, then close the session
+///
+/// Unlike [`capture`], this makes a single attempt and returns both images.
+pub async fn connect_and_capture(config: &CaptureConfig) -> Result<Capture, ResnapError> {
+    connect_and_capture_with_progress(config, &no_progress).await
+}
+
+/// Like [`connect_and_capture`], calling `progress` as each [`Stage`] is reached
+pub async fn connect_and_capture_with_progress(
+    config: &CaptureConfig,
+    progress: &Progress<'_>,
+) -> Result<Capture, ResnapError> {
+    progress(Stage::Connecting);
+    let session = connect(config).await?;
+    let capture =
+        capture_once_cached_with_progress(&session, config, &mut CaptureCache::default(), progress)
+            .await?;
+    session.close().await?;
+    progress(Stage::Done);
+
+    Ok(capture)
+}
+
+/// Capture and crop the screen over an already open session
+///
+/// Reusing one session for several captures avoids the SSH handshake on every shot.
+pub async fn capture_once(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+) -> Result<Capture, ResnapError> {
+    capture_once_cached(session, config, &mut CaptureCache::default()).await
+}
+
+/// Capture and crop the screen, reusing the device details resolved by earlier captures
+///
+/// Use one [`CaptureCache`] per session to skip the model, PID and address lookups on
+/// repeated captures.
+pub async fn capture_once_cached(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+) -> Result<Capture, ResnapError> {
+    capture_once_cached_with_progress(session, config, cache, &no_progress).await
+}
+
+/// Like [`capture_once_cached`], calling `progress` as each [`Stage`] is reached
+///
+/// Unlike [`connect_and_capture_with_progress`], [`Stage::Connecting`] and [`Stage::Done`]
+/// are not reported since the session is already open.
+pub async fn capture_once_cached_with_progress(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+    progress: &Progress<'_>,
+) -> Result<Capture, ResnapError> {
+    let raw = read_kept_framebuffer(session, config, cache, progress).await?;
+    let model = cache.model.unwrap_or_default();
+
+    // The battery is only informational, so don't fail the capture over it. It's read before
+    // decoding so that all the time spent on the tablet is part of the transfer stage.
+    let battery = match with_timeout(config.timeout, read_battery(session)).await {
+        Ok(Ok(battery)) => battery,
+        Ok(Err(e)) | Err(e) => {
+            log::debug!("🔋 Could not read the battery charge: {}", e);
+            None
+        }
+    };
+
+    progress(Stage::Decoding);
+    let screen = decode_framebuffer(&raw.data, &raw.format, &config.decode)?;
+
+    Ok(Capture {
+        battery,
+        ..Capture::from_screen_with_progress(screen, model, config.crop.as_ref(), progress)?
+    })
+}
+
+/// Open an SSH session to the tablet
+pub async fn connect(config: &CaptureConfig) -> Result<Session, ResnapError> {
+    let remarkable_ip = &config.ip_address;
+    validate_host(remarkable_ip)?;
+
+    #[cfg(feature = "mdns")]
+    let resolved = resolve_host(remarkable_ip).await;
+    #[cfg(feature = "mdns")]
+    let remarkable_ip = &resolved;
+
+    let mut builder = SessionBuilder::default();
+    builder.known_hosts_check(config.known_hosts.clone());
+
+    if let Some(ssh_config) = &config.ssh_config {
+        if !ssh_config.is_file() {
+            return Err(ResnapError::InvalidConfig(format!(
+                "SSH config {} not found",
+                ssh_config.display()
+            )));
+        }
+    }
+
+    // Without a user the SSH config decides, falling back to root rather than the local user
+    let fallback_config = match &config.user {
+        Some(user) => {
+            builder.user(user.clone());
+            if let Some(ssh_config) = &config.ssh_config {
+                builder.config_file(ssh_config);
+            }
+            None
+        }
+        None => {
+            let path = write_fallback_ssh_config(
+                &config.ip_address,
+                remarkable_ip,
+                config.ssh_config.as_deref(),
+            )?;
+            builder.config_file(&path);
+            Some(path)
+        }
+    };
+
+    if let Some(port) = config.port {
+        builder.port(port);
+    }
+
+    if let Some(timeout) = config.timeout {
+        builder.connect_timeout(timeout);
+    }
+
+    if let Some(identity) = &config.identity {
+        // Fail early rather than letting ssh fall back to other keys
+        if !identity.is_file() {
+            return Err(ResnapError::IdentityNotFound(identity.clone()));
+        }
+        builder.keyfile(identity);
+    }
+
+    if let Some(jump) = &config.jump {
+        if jump.is_empty() || jump.contains(char::is_whitespace) {
+            return Err(ResnapError::InvalidConfig(format!(
+                "jump host {:?} is not [user@]host[:port]",
+                jump
+            )));
+        }
+        // The jump hosts take their keys and host key checks from ~/.ssh/config, like `ssh -J`
+        builder.jump_hosts(jump.split(','));
+    }
+
+    let session = with_timeout(
+        config.timeout,
+        builder.connect(format!("ssh://{}", url_host(remarkable_ip))),
+    )
+    .await;
+
+    // ssh has read the config once the connection is up or has failed
+    if let Some(path) = fallback_config {
+        let _ = fs::remove_file(path);
+    }
+    let session = session??;
+
+    let target = match config.port {
+        Some(port) => format!("{}:{}", remarkable_ip, port),
+        None => remarkable_ip.to_string(),
+    };
+    match &config.jump {
+        Some(jump) => log::info!("✅ Connected to reMarkable at {} through {}", target, jump),
+        None => log::info!("✅ Connected to reMarkable at {}", target),
+    }
+
+    Ok(session)
+}
+
+/// Write an SSH config that reads `ssh_config` (by default `~/.ssh/config`) and the system
+/// config, then logs in to `host` as root if they don't give it a `User`
+///
+/// ssh matches `Host` blocks against the address it connects to, so the fallback also covers
+/// `resolved`, the address mDNS may have resolved `host` to.
+///
+/// ssh uses the first value it finds for each setting, so the included files win over the
+/// fallback, and jump hosts keep ssh's usual default user. Returns the path of the file, which
+/// is removed once connected.
+fn write_fallback_ssh_config(
+    host: &str,
+    resolved: &str,
+    ssh_config: Option<&Path>,
+) -> Result<PathBuf, ResnapError> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let include = match ssh_config {
+        Some(path) => fs::canonicalize(path)?.display().to_string(),
+        None => "~/.ssh/config".to_string(),
+    };
+    let hosts = if resolved == host {
+        host.to_string()
+    } else {
+        format!("{} {}", host, resolved)
+    };
+    let path = std::env::temp_dir().join(format!(
+        "resnap-ssh-config-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(
+        &path,
+        format!(
+            "Include \"{}\"\nInclude /etc/ssh/ssh_config\n\nHost {}\n    User root\n",
+            include, hosts
+        ),
+    )?;
+
+    Ok(path)
+}
+
+/// Check that `host` is an IP address or a syntactically valid hostname
+///
+/// IPv6 addresses may be written with or without brackets. Hostnames are checked against the
+/// DNS rules, allowing underscores as in SSH config aliases; whether they resolve is left to
+/// the connection.
+pub fn validate_host(host: &str) -> Result<(), ResnapError> {
+    let invalid = || ResnapError::InvalidHost(host.to_string());
+
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if unbracketed.parse::<IpAddr>().is_ok() {
+        return Ok(());
+    }
+
+    let name = host.strip_suffix('.').unwrap_or(host);
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    // Names made only of digits and dots are malformed IPv4 addresses, not hostnames
+    let looks_numeric = name.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+    if name.is_empty() || name.len() > 253 || looks_numeric || !name.split('.').all(valid_label) {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Resolve a `.local` hostname with mDNS, keeping the name for the system resolver if that fails
+#[cfg(feature = "mdns")]
+async fn resolve_host(host: &str) -> String {
+    if !is_mdns_host(host) {
+        return host.to_string();
+    }
+
+    match resolve_mdns(host, MDNS_TIMEOUT).await {
+        Ok(Some(addr)) => {
+            log::info!("📡 Resolved {} to {} with mDNS", host, addr);
+            addr.to_string()
+        }
+        Ok(None) => {
+            log::warn!("⚠️ No mDNS answer for {}, using the system resolver", host);
+            host.to_string()
+        }
+        Err(e) => {
+            log::warn!("⚠️ {}, using the system resolver", e);
+            host.to_string()
+        }
+    }
+}
+
+/// Host as written in an `ssh://` URL, with IPv6 addresses in brackets
+fn url_host(host: &str) -> String {
+    match host.parse::<Ipv6Addr>() {
+        Ok(addr) => format!("[{}]", addr),
+        Err(_) => host.to_string(),
+    }
+}
+
+/// Run a future, failing with [`ResnapError::Timeout`] if it takes longer than `timeout`
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = T>,
+) -> Result<T, ResnapError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| ResnapError::Timeout(timeout)),
+        None => Ok(future.await),
+    }
+}
+
+/// Read the framebuffer with the configured transfer method
+async fn transfer_framebuffer(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    location: &FramebufferLocation,
+    window_bytes: usize,
+    progress: &Progress<'_>,
+) -> Result<Vec<u8>, ResnapError> {
+    session
+        .read_mem(
+            &format!("/proc/{}/mem", location.pid),
+            location.skip_bytes,
+            window_bytes,
+            config.transfer,
+            config.chunk_size,
+            config.compress,
+            progress,
+        )
+        .await
+}
+
+/// Read the framebuffer with `read`, reading again once if the data isn't `window_bytes` long
+///
+/// A truncated transfer would otherwise decode to a garbled image.
+async fn read_exact_size<F, Fut>(window_bytes: usize, read: F) -> Result<Vec<u8>, ResnapError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Result<Vec<u8>, ResnapError>, ResnapError>>,
+{
+    let fb_data = read().await??;
+    if fb_data.len() == window_bytes {
+        return Ok(fb_data);
+    }
+
+    log::warn!(
+        "⚠️ Read {} of {} framebuffer bytes, reading again",
+        fb_data.len(),
+        window_bytes
+    );
+    let fb_data = read().await??;
+    if fb_data.len() != window_bytes {
+        return Err(ResnapError::ShortRead {
+            got: fb_data.len(),
+            expected: window_bytes,
+        });
+    }
+
+    Ok(fb_data)
+}
+
+/// Use the configured model or detect it, falling back to the default model
+async fn resolve_model(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+) -> Result<Model, ResnapError> {
+    match config.model {
+        Some(model) => {
+            log::info!("📱 Using model {} from configuration", model);
+            Ok(model)
+        }
+        None => match with_timeout(config.timeout, detect_model(session)).await? {
+            Ok(model) => {
+                log::info!("📱 Detected model {}", model);
+                Ok(model)
+            }
+            Err(ResnapError::UnknownModel(machine)) => {
+                let model = Model::default();
+                log::warn!(
+                    "⚠️ Unknown machine {:?}, falling back to {}",
+                    machine,
+                    model
+                );
+                Ok(model)
+            }
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Capture the full, oriented and contrast-adjusted screen image over an open session
+///
+/// The model and framebuffer location are taken from `cache` when known and stored in it
+/// otherwise.
+pub async fn capture_screen(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+) -> Result<DynamicImage, ResnapError> {
+    read_screen(session, config, cache, &no_progress).await
+}
+
+/// Capture the full screen image over an open session, reporting each stage
+async fn read_screen(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+    progress: &Progress<'_>,
+) -> Result<DynamicImage, ResnapError> {
+    let raw = read_kept_framebuffer(session, config, cache, progress).await?;
+
+    progress(Stage::Decoding);
+    decode_framebuffer(&raw.data, &raw.format, &config.decode)
+}
+
+/// Read the raw framebuffer over an open session, saving a copy to `config.keep_raw` if set
+async fn read_kept_framebuffer(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+    progress: &Progress<'_>,
+) -> Result<RawFramebuffer, ResnapError> {
+    let raw = read_raw_framebuffer(session, config, cache, progress).await?;
+
+    if let Some(raw_path) = &config.keep_raw {
+        fs::write(raw_path, &raw.data)?;
+        log::info!("💾 Saved raw framebuffer to {}", raw_path.display());
+    }
+
+    Ok(raw)
+}
+
+/// Unprocessed framebuffer contents and their layout
+#[derive(Debug, Clone)]
+pub struct RawFramebuffer {
+    /// Raw pixel data, as read from the tablet
+    pub data: Vec<u8>,
+    /// Width, height and pixel format of `data`
+    pub format: FramebufferFormat,
+}
+
+/// Read the raw framebuffer of a tablet of the given model over an open session
+///
+/// Locates the framebuffer and copies it off the tablet without decoding it, using the
+/// command line defaults for the backend, transfer method and timeout.
+pub async fn read_framebuffer(
+    session: &impl RemoteExec,
+    model: Model,
+) -> Result<RawFramebuffer, ResnapError> {
+    let config = CaptureConfigBuilder::new("").model(model).build_unchecked();
+    read_raw_framebuffer(session, &config, &mut CaptureCache::default(), &no_progress).await
+}
+
+/// Locate and read the raw framebuffer over an open session, reporting each stage
+async fn read_raw_framebuffer(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+    progress: &Progress<'_>,
+) -> Result<RawFramebuffer, ResnapError> {
+    let model = match cache.model {
+        Some(model) => model,
+        None => {
+            let model = resolve_model(session, config).await?;
+            cache.model = Some(model);
+            model
+        }
+    };
+
+    if config.wake {
+        with_timeout(config.timeout, wake_device(session)).await??;
+    }
+
+    let backend = match cache.backend {
+        Some(backend) => backend,
+        None => {
+            let backend = resolve_backend(session, config, model).await?;
+            cache.backend = Some(backend);
+            backend
+        }
+    };
+
+    // Calculate window size
+    let fb_format = match (backend, &cache.fb_format) {
+        (Backend::Rm2fb, _) => FramebufferFormat::rm2fb(),
+        (_, Some(fb_format)) => fb_format.clone(),
+        (_, None) => {
+            let fb_format = with_timeout(
+                config.timeout,
+                detect_framebuffer_format(session, model, config.framebuffer),
+            )
+            .await??;
+            cache.fb_format = Some(fb_format.clone());
+            fb_format
+        }
+    };
+    let window_bytes = fb_format.window_bytes();
+    log::info!(
+        "📏 Window size: {}x{} ({}B per pixel, {} total)",
+        fb_format.width,
+        fb_format.height,
+        fb_format.bytes_per_pixel,
+        window_bytes
+    );
+
+    let fb_data = match backend {
+        Backend::Rm2fb => {
+            progress(Stage::ReadingFramebuffer {
+                bytes: window_bytes,
+            });
+            read_exact_size(window_bytes, || {
+                with_timeout(
+                    config.timeout,
+                    session.read_mem(
+                        RM2FB_SHM_PATH,
+                        0,
+                        window_bytes,
+                        Transfer::Dd,
+                        config.chunk_size,
+                        false,
+                        progress,
+                    ),
+                )
+            })
+            .await?
+        }
+        _ => read_process_memory(session, config, cache, window_bytes, progress).await?,
+    };
+
+    Ok(RawFramebuffer {
+        data: fb_data,
+        format: fb_format,
+    })
+}
+
+/// Use the configured backend or pick one, preferring rm2fb on a reMarkable 2 that runs it
+async fn resolve_backend(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    model: Model,
+) -> Result<Backend, ResnapError> {
+    let backend = match config.backend {
+        // rm2fb only mirrors the main display
+        Backend::Auto if model == Model::Rm2 && config.framebuffer == 0 => {
+            if with_timeout(config.timeout, has_rm2fb(session)).await?? {
+                Backend::Rm2fb
+            } else {
+                Backend::Mem
+            }
+        }
+        Backend::Auto => Backend::Mem,
+        backend => backend,
+    };
+    log::info!("🧭 Using the {} backend", backend);
+
+    Ok(backend)
+}
+
+/// Read the framebuffer from xochitl's memory, reusing its cached location when possible
+async fn read_process_memory(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    cache: &mut CaptureCache,
+    window_bytes: usize,
+    progress: &Progress<'_>,
+) -> Result<Vec<u8>, ResnapError> {
+    let cached_data = match &cache.location {
+        Some(location) => {
+            log::info!("📍 Reusing framebuffer of PID {}", location.pid);
+            progress(Stage::ReadingFramebuffer {
+                bytes: window_bytes,
+            });
+            let result = with_timeout(
+                config.timeout,
+                transfer_framebuffer(session, config, location, window_bytes, progress),
+            )
+            .await
+            .and_then(|read| read);
+
+            // A failed read usually means xochitl restarted, so forget it and locate it again
+            match result {
+                Ok(fb_data) if fb_data.len() == window_bytes => Some(fb_data),
+                Ok(_) => {
+                    log::info!("🔄 Cached framebuffer read came up short, locating it again");
+                    cache.location = None;
+                    None
+                }
+                Err(e) => {
+                    log::info!(
+                        "🔄 Cached framebuffer read failed ({}), locating it again",
+                        e
+                    );
+                    cache.location = None;
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let fb_data = match cached_data {
+        Some(fb_data) => fb_data,
+        None => {
+            progress(Stage::ResolvingPid);
+            let location = with_timeout(
+                config.timeout,
+                locate_framebuffer(session, config.framebuffer, window_bytes),
+            )
+            .await??;
+            progress(Stage::ReadingFramebuffer {
+                bytes: window_bytes,
+            });
+            let fb_data = read_exact_size(window_bytes, || {
+                with_timeout(
+                    config.timeout,
+                    transfer_framebuffer(session, config, &location, window_bytes, progress),
+                )
+            })
+            .await?;
+            cache.location = Some(location);
+            fb_data
+        }
+    };
+
+    Ok(fb_data)
+}
@@ -0,0 +1,52 @@
+use crate::metadata::json_string;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fmt::Write;
+
+/// How log records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines, as `env_logger` writes them
+    #[default]
+    Pretty,
+    /// One JSON object per record, for log pipelines
+    Json,
+}
+
+/// Serialize a log record as a single-line JSON object
+///
+/// The object holds the `timestamp`, `level`, `target` and `message`, and under `fields` the
+/// module, file and line the record came from when known.
+pub fn json_log_record(record: &log::Record, timestamp: DateTime<Utc>) -> String {
+    let optional =
+        |value: Option<&str>| value.map(json_string).unwrap_or_else(|| "null".to_string());
+
+    let mut json = String::from("{");
+    let _ = write!(
+        json,
+        "\"timestamp\":{}",
+        json_string(&timestamp.to_rfc3339_opts(SecondsFormat::Millis, true))
+    );
+    let _ = write!(json, ",\"level\":{}", json_string(record.level().as_str()));
+    let _ = write!(json, ",\"target\":{}", json_string(record.target()));
+    let _ = write!(
+        json,
+        ",\"message\":{}",
+        json_string(&record.args().to_string())
+    );
+    let _ = write!(
+        json,
+        ",\"fields\":{{\"module\":{}",
+        optional(record.module_path())
+    );
+    let _ = write!(json, ",\"file\":{}", optional(record.file()));
+    let _ = write!(
+        json,
+        ",\"line\":{}}}",
+        record
+            .line()
+            .map(|line| line.to_string())
+            .unwrap_or_else(|| "null".to_string())
+    );
+    json.push('}');
+    json
+}
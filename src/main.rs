@@ -1,345 +1,1689 @@
-use chrono::Utc;
-use clap::Parser;
-use image::{ImageBuffer, Luma, Rgba, RgbaImage};
-use imageproc::contours;
-use openssh::Session;
+use chrono::{DateTime, Utc};
+use clap::{CommandFactory, Parser, Subcommand};
+use futures::future::join_all;
+#[cfg(feature = "progress")]
+use indicatif::{ProgressBar, ProgressStyle};
+use resnap_rs::{
+    Backend, Capture, CaptureCache, CaptureConfig, CaptureMetadata, CropOptions, DecodeOptions,
+    Decoder, DeviceInfo, FileDigest, GifAnimation, KnownHosts, LocalExec, LogFormat, Model,
+    NameTemplate, OutputFormat, OutputOptions, PdfDocument, PngCompression, RemoteExec,
+    ResnapError, RetryPolicy, Rotation, Stage, StageTimings, SvgOptions, ThresholdMode, Transfer,
+    UiMask,
+};
+#[cfg(feature = "progress")]
+use std::io::IsTerminal;
 use std::{
-    fs::{self, File},
+    collections::hash_map::DefaultHasher,
+    env,
+    ffi::OsString,
+    fs,
+    hash::{Hash, Hasher},
     io::Write,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
+// Exit statuses, so scripts wrapping resnap can tell failure modes apart. 0 is success and 2
+// is left to clap, which uses it for invalid command lines.
+
+/// Exit status of any failure without a more specific status
+const EXIT_FAILURE: u8 = 1;
+
+/// Exit status when the tablet couldn't be reached or stopped responding
+const EXIT_CONNECTION: u8 = 3;
+
+/// Exit status when no `xochitl` process is running on the tablet
+const EXIT_NO_XOCHITL: u8 = 4;
+
+/// Exit status when `xochitl` doesn't map the framebuffer where expected
+const EXIT_NO_MAPPING: u8 = 5;
+
+/// Exit status when no significant content was found
+const EXIT_NO_CONTENT: u8 = 6;
+
+/// Exit status when the raw framebuffer couldn't be converted, by ffmpeg or natively
+const EXIT_DECODE: u8 = 7;
+
 /// A utility to capture and process screenshots from reMarkable tablets
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_override_self = true,
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    // Options of the default `capture` subcommand
+    #[clap(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Capture the screen; the default when no subcommand is given
+    #[clap(args_override_self = true)]
+    Capture(Args),
+    /// Decode and crop a raw framebuffer dump saved with --keep-raw instead of capturing
+    #[clap(args_override_self = true)]
+    Reprocess {
+        /// Raw framebuffer dump to reprocess
+        #[clap(value_name = "RAW")]
+        raw: PathBuf,
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Connect and print the tablet's model, firmware, framebuffer, battery charge and whether
+    /// the framebuffer mapping is found, without capturing
+    #[clap(args_override_self = true)]
+    Info(Args),
+}
+
+// Options shared by all subcommands
+#[derive(clap::Args, Debug)]
 struct Args {
-    /// IP address of the reMarkable tablet
-    #[clap(short = 'I', long = "ip-address", required = true)]
-    ip_address: String,
+    /// Read option defaults from this TOML file instead of the first `resnap.toml` found in the
+    /// working directory or `$XDG_CONFIG_HOME/resnap/`
+    #[clap(long = "config", value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// IP address of the reMarkable tablet, or a host alias from the SSH config; repeat or
+    /// separate with commas to capture from several tablets at once
+    #[clap(
+        short = 'I',
+        long = "ip-address",
+        env = "RESNAP_IP",
+        value_delimiter = ','
+    )]
+    ip_address: Vec<String>,
+
+    /// Reprocess a saved raw framebuffer dump instead of capturing over SSH, like the
+    /// `reprocess` subcommand; any IP address is ignored
+    #[clap(long = "from-raw")]
+    from_raw: Option<PathBuf>,
+
+    /// Capture the screen of the machine resnap runs on, when it runs on the tablet itself,
+    /// instead of connecting over SSH; any IP address is ignored
+    #[clap(long = "local")]
+    local: bool,
+
+    /// SSH login user on the reMarkable tablet (default: the SSH config's User for the host, or
+    /// root)
+    #[clap(short = 'u', long = "user")]
+    user: Option<String>,
+
+    /// SSH port of the reMarkable tablet (default: the SSH config's Port for the host, or 22)
+    #[clap(short = 'P', long = "port")]
+    port: Option<u16>,
+
+    /// SSH config file to read instead of ~/.ssh/config, for host aliases given to
+    /// --ip-address
+    #[clap(long = "ssh-config", value_name = "PATH", conflicts_with = "local")]
+    ssh_config: Option<PathBuf>,
+
+    /// How the tablet's SSH host key is checked
+    #[clap(long = "known-hosts", value_enum, default_value_t = KnownHostsPolicy::Add)]
+    known_hosts: KnownHostsPolicy,
+
+    /// Seconds to wait for the connection and each step on the tablet (0 waits forever)
+    #[clap(short = 'T', long = "timeout", default_value_t = 15)]
+    timeout: u64,
+
+    /// Number of times to retry after a connection failure or timeout
+    #[clap(short = 'r', long = "retries", default_value_t = 0)]
+    retries: u32,
+
+    /// Milliseconds to wait before the first retry, doubled on each further retry
+    #[clap(long = "retry-delay", default_value_t = 500)]
+    retry_delay: u64,
+
+    /// SSH private key file used to connect to the tablet
+    #[clap(short = 'i', long = "identity")]
+    identity: Option<PathBuf>,
+
+    /// Jump host to reach the tablet through, as with `ssh -J` (several separated by commas)
+    #[clap(
+        short = 'J',
+        long = "jump",
+        value_name = "[USER@]HOST[:PORT]",
+        conflicts_with = "local"
+    )]
+    jump: Option<String>,
 
     /// Directory to save the output files
     #[clap(short = 'd', long = "directory", default_value = ".")]
     output_dir: PathBuf,
+
+    /// Model of the reMarkable tablet (detected automatically when omitted, rm2 for --from-raw)
+    #[clap(short = 'm', long = "model", value_enum)]
+    model: Option<Model>,
+
+    /// Wake the display before capturing (runs `echo 0 > /sys/class/graphics/fb0/blank`)
+    #[clap(long = "wake")]
+    wake: bool,
+
+    /// Where the screen is read from on the tablet
+    #[clap(long = "backend", value_enum, default_value_t = Backend::Auto)]
+    backend: Backend,
+
+    /// Index n of the framebuffer device /dev/fb<n> to capture, for alternate displays
+    /// (uses the `mem` backend)
+    #[clap(long = "fb", value_name = "N", default_value_t = 0)]
+    fb: u32,
+
+    /// How the framebuffer is copied off the tablet with the `mem` backend
+    #[clap(long = "transfer", value_enum, default_value_t = Transfer::Dd)]
+    transfer: Transfer,
+
+    /// Read the framebuffer with a separate dd for each block of this many bytes instead of one
+    /// big read, for firmware that truncates it; at least 4096
+    #[clap(
+        long = "chunk-size",
+        value_name = "BYTES",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new()
+            .range(resnap_rs::MIN_CHUNK_SIZE as u64..)
+    )]
+    chunk_size: Option<usize>,
+
+    /// Compress the framebuffer with gzip on the tablet before transferring it, for slow
+    /// connections (falls back to an uncompressed transfer without gzip)
+    #[clap(long = "compress")]
+    compress: bool,
+
+    /// How the raw framebuffer is converted into an image
+    #[clap(long = "decoder", value_enum, default_value_t = Decoder::Ffmpeg)]
+    decoder: Decoder,
+
+    /// Input level (0-1) mapped to black by the contrast curve (default: 0.045 on rm2, 0 on
+    /// rm1 and rmpp)
+    #[clap(long = "black-point")]
+    black_point: Option<f32>,
+
+    /// Input level (0-1) mapped to white by the contrast curve (default: 0.06 on rm2, 1 on rm1
+    /// and rmpp)
+    #[clap(long = "white-point")]
+    white_point: Option<f32>,
+
+    /// Skip the contrast curve and keep the framebuffer's raw levels, for tuning the curve
+    #[clap(long = "no-curves", conflicts_with_all = ["black_point", "white_point"])]
+    no_curves: bool,
+
+    /// Extra ffmpeg filters appended after the orientation and contrast curve, such as `unsharp`
+    #[clap(long = "vf", value_name = "FILTERCHAIN")]
+    vf: Option<String>,
+
+    /// Use --vf instead of the contrast curve; the screen is still oriented unless the chain
+    /// transposes, flips or rotates it
+    #[clap(long = "vf-replace", requires = "vf")]
+    vf_replace: bool,
+
+    /// Rotate the screen clockwise by this many degrees, for landscape notebooks
+    #[clap(long = "rotate", value_enum, default_value_t = Rotation::None)]
+    rotate: Rotation,
+
+    /// Grayscale threshold (0-255) below which pixels count as handwriting
+    #[clap(short = 't', long = "threshold", default_value_t = 200)]
+    threshold: u8,
+
+    /// Gamma applied before thresholding; above 1 darkens midtones to pick up faint pencil
+    #[clap(long = "gamma", default_value_t = 1.0)]
+    gamma: f32,
+
+    /// Stretch the grayscale levels before thresholding to even out brightness between captures
+    #[clap(long = "auto-contrast")]
+    auto_contrast: bool,
+
+    /// Percentage of pixels clipped to black and to white by --auto-contrast
+    #[clap(
+        long = "auto-contrast-clip",
+        default_value_t = 1.0,
+        requires = "auto_contrast"
+    )]
+    auto_contrast_clip: f32,
+
+    /// How the threshold is chosen; `otsu` computes it from each image and ignores --threshold
+    #[clap(long = "threshold-mode", value_enum, default_value_t = ThresholdMode::Fixed)]
+    threshold_mode: ThresholdMode,
+
+    /// Remove specks smaller than a square kernel of this radius before finding contours
+    #[clap(long = "denoise", value_name = "KERNEL")]
+    denoise: Option<u8>,
+
+    /// Minimum number of points for a contour to count as content (0 keeps all contours)
+    #[clap(long = "min-contour-size", default_value_t = 100)]
+    min_contour_size: usize,
+
+    /// Save an image of the detected contours to this path for tuning the detection
+    #[clap(
+        long = "debug-contours",
+        value_name = "PATH",
+        conflicts_with = "no_crop"
+    )]
+    debug_contours: Option<PathBuf>,
+
+    /// Also save the detected contours as vector paths to this SVG file, framed like the cropped
+    /// image
+    #[clap(long = "svg", value_name = "PATH", conflicts_with = "no_crop")]
+    svg: Option<PathBuf>,
+
+    /// Stroke color of the SVG paths
+    #[clap(long = "svg-stroke", default_value = "black")]
+    svg_stroke: String,
+
+    /// Stroke width of the SVG paths in pixels
+    #[clap(long = "svg-stroke-width", default_value_t = 1.0)]
+    svg_stroke_width: f32,
+
+    /// Also save each separate piece of content as its own `_region_<i>` image
+    #[clap(long = "split", conflicts_with = "no_crop")]
+    split: bool,
+
+    /// Pieces of content closer than this many pixels are saved as one region with --split
+    #[clap(long = "split-gap", default_value_t = 50)]
+    split_gap: u32,
+
+    /// How the tablet's UI is kept out of detection: `region` ignores the top-left menu button
+    /// region, `auto` finds solid gray toolbar bands along the edges, `none` ignores nothing
+    #[clap(long = "mask-ui", value_enum, default_value_t = UiMask::Region)]
+    mask_ui: UiMask,
+
+    /// Width of the top-left UI region (menu button) excluded from detection with --mask-ui
+    /// region
+    #[clap(long = "exclude-x", default_value_t = 200)]
+    exclude_x: u32,
+
+    /// Height of the top-left UI region (menu button) excluded from detection with --mask-ui
+    /// region
+    #[clap(long = "exclude-y", default_value_t = 200)]
+    exclude_y: u32,
+
+    /// Don't exclude the top-left UI region from detection
+    #[clap(long = "no-exclude", conflicts_with_all = ["exclude_x", "exclude_y"])]
+    no_exclude: bool,
+
+    /// Padding in pixels around the cropped content (0 for a tight crop)
+    #[clap(short = 'p', long = "padding", default_value_t = 50)]
+    padding: u32,
+
+    /// Trim fully white borders off the cropped image, down to the ink
+    #[clap(long = "trim", conflicts_with = "no_crop")]
+    trim: bool,
+
+    /// Crop every capture to this rectangle instead of the detected content
+    #[clap(long = "fixed-crop", value_name = "X,Y,W,H", conflicts_with_all = ["no_crop", "trim"])]
+    fixed_crop: Option<String>,
+
+    /// Crop straight to this rectangle of the rotated screen without detecting content, for
+    /// fixed layouts such as templates
+    #[clap(
+        long = "region",
+        value_name = "X,Y,W,H",
+        conflicts_with_all = ["no_crop", "trim", "fixed_crop"]
+    )]
+    region: Option<String>,
+
+    /// With --watch, crop every capture to the bounding box of the first one with content, so
+    /// all frames share the same dimensions
+    #[clap(
+        long = "lock-crop",
+        requires = "watch",
+        conflicts_with_all = ["no_crop", "fixed_crop", "region", "trim"]
+    )]
+    lock_crop: bool,
+
+    /// Levels below pure white (0-255) that still count as white with --trim
+    #[clap(long = "trim-tolerance", default_value_t = 25, requires = "trim")]
+    trim_tolerance: u8,
+
+    /// Detect light writing on a dark background, for dark-mode notebooks
+    #[clap(long = "invert")]
+    invert: bool,
+
+    /// Skip content detection and keep only the full screen image
+    #[clap(long = "no-crop")]
+    no_crop: bool,
+
+    /// Overwrite existing images and animations instead of failing
+    #[clap(long = "force")]
+    force: bool,
+
+    /// Which images are saved; with `crop`, the full screen is still saved when no content is
+    /// found
+    #[clap(long = "keep", value_enum, default_value_t = Keep::Both, conflicts_with = "no_crop")]
+    keep: Keep,
+
+    /// When no content is found, use the full screen in place of the cropped image and exit
+    /// successfully instead of with status 6
+    #[clap(long = "allow-empty", conflicts_with = "no_crop")]
+    allow_empty: bool,
+
+    /// Keep the raw framebuffer dump in the output directory
+    #[clap(long = "keep-raw")]
+    keep_raw: bool,
+
+    /// Image format of the saved files
+    #[clap(short = 'f', long = "format", value_enum, default_value_t = OutputFormat::Png)]
+    format: OutputFormat,
+
+    /// JPEG quality (1-100)
+    #[clap(short = 'q', long = "quality", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+
+    /// PNG compression level; `best` makes the smallest files for archiving
+    #[clap(long = "png-compression", value_enum, default_value_t = PngCompression::Default)]
+    png_compression: PngCompression,
+
+    /// Apply --quality and --png-compression only to the cropped images, saving the full screen
+    /// with the defaults
+    #[clap(long = "encode-crop-only")]
+    encode_crop_only: bool,
+
+    /// Resolution recorded in saved PNG and JPEG files (defaults to the tablet's, 226 on rm1/rm2)
+    #[clap(long = "dpi", value_parser = clap::value_parser!(u32).range(1..))]
+    dpi: Option<u32>,
+
+    /// Invert the colors of the saved images
+    #[clap(long = "invert-output")]
+    invert_output: bool,
+
+    /// Log more: -v shows progress, -vv debug output including ffmpeg's log, -vvv everything;
+    /// overrides RUST_LOG
+    #[clap(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only log errors, overriding RUST_LOG
+    #[clap(long = "quiet", conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Write log records as human-readable lines or as one JSON object per line
+    #[clap(long = "log-format", value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    /// Output file name template: strftime tokens, {model} and an auto-incrementing {n}
+    #[clap(short = 'o', long = "name-template", default_value = NameTemplate::DEFAULT)]
+    name_template: String,
+
+    /// Warn when the tablet's battery charge is below this percentage
+    #[clap(
+        long = "battery-warn",
+        value_name = "PCT",
+        default_value_t = 20,
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    battery_warn: u8,
+
+    /// With --watch, stop capturing once the battery charge drops below this percentage
+    #[clap(
+        long = "battery-stop",
+        value_name = "PCT",
+        requires = "watch",
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    battery_stop: Option<u8>,
+
+    /// With --watch, don't save captures whose screen is identical to the previous capture's
+    #[clap(long = "skip-unchanged", requires = "watch")]
+    skip_unchanged: bool,
+
+    /// Keep capturing every N seconds until interrupted with Ctrl-C
+    #[clap(short = 'w', long = "watch", conflicts_with = "from_raw", value_parser = clap::value_parser!(u64).range(1..))]
+    watch: Option<u64>,
+
+    /// Also collect the cropped image, or the full screen when not cropping, of every capture
+    /// as a page of this PDF, written when the last capture is done or watching is stopped
+    #[clap(long = "pdf", value_name = "PATH")]
+    pdf: Option<PathBuf>,
+
+    /// With --watch, also encode the cropped image, or the full screen when not cropping, of
+    /// every capture as a frame of this animated GIF, finished when watching is stopped; the
+    /// crop is locked as with --lock-crop unless --fixed-crop is given
+    #[clap(
+        long = "animate",
+        value_name = "PATH",
+        requires = "watch",
+        conflicts_with = "trim"
+    )]
+    animate: Option<PathBuf>,
+
+    /// Milliseconds each frame of the --animate GIF is shown for
+    #[clap(long = "animate-delay", default_value_t = 500, requires = "animate")]
+    animate_delay: u64,
+
+    /// Copy the cropped image, or the full screen when not cropping, to the clipboard
+    #[cfg(feature = "clipboard")]
+    #[clap(long = "clipboard", conflicts_with = "stdout")]
+    clipboard: bool,
+
+    /// Recognize the text in the cropped image, or the full screen when not cropping, and save
+    /// it next to the image as `.txt`
+    #[cfg(feature = "ocr")]
+    #[clap(long = "ocr", conflicts_with = "stdout")]
+    ocr: bool,
+
+    /// Tesseract language code used with --ocr, such as `deu` or `deu+eng`
+    #[cfg(feature = "ocr")]
+    #[clap(long = "ocr-lang", default_value = resnap_rs::DEFAULT_OCR_LANG, requires = "ocr")]
+    ocr_lang: String,
+
+    /// Upload the cropped image, or the full screen when not cropping, to `bucket/prefix`
+    ///
+    /// The endpoint, region and credentials come from the standard `AWS_*` environment variables.
+    #[cfg(feature = "s3")]
+    #[clap(long = "s3", value_name = "BUCKET/PREFIX", conflicts_with = "stdout")]
+    s3: Option<String>,
+
+    /// POST the cropped image, or the full screen when not cropping, and its JSON metadata to
+    /// this URL
+    #[cfg(feature = "webhook")]
+    #[clap(long = "webhook", value_name = "URL", conflicts_with = "stdout")]
+    webhook: Option<String>,
+
+    /// Extra header sent to the webhook as `key:value`, such as an auth token (repeatable)
+    #[cfg(feature = "webhook")]
+    #[clap(
+        long = "webhook-header",
+        value_name = "KEY:VALUE",
+        requires = "webhook"
+    )]
+    webhook_header: Vec<String>,
+
+    /// Print a JSON object describing each capture instead of the output path
+    #[clap(long = "json")]
+    json: bool,
+
+    /// Append the name, size and SHA-256 hash of each saved image to this manifest file, and
+    /// add the hashes to the --json output
+    #[clap(long = "manifest", value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Time each stage of a capture and print the breakdown to stderr, and into the --json
+    /// output
+    #[clap(long = "timings")]
+    timings: bool,
+
+    /// Write the cropped image, or the full screen when not cropping, to stdout in the chosen
+    /// format instead of saving any files, for piping into other tools
+    #[clap(
+        long = "stdout",
+        conflicts_with_all = [
+            "json", "dry_run", "watch", "debug_contours", "svg", "split", "pdf", "animate"
+        ]
+    )]
+    stdout: bool,
+
+    /// Connect, capture and analyze, but write no files and leave the tablet untouched; prints
+    /// the files that would be saved and the capture's JSON description
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+}
+
+/// How `--watch` keeps capturing
+#[derive(Debug, Clone, Copy)]
+struct WatchOptions {
+    /// Seconds between captures
+    interval: u64,
+    /// Crop every capture to the bounding box of the first one with content
+    lock_crop: bool,
+    /// Stop once the battery drops below this percentage
+    battery_stop: Option<u8>,
+    /// Don't save captures whose screen is identical to the previous one
+    skip_unchanged: bool,
+}
+
+/// Where and how captured images are saved
+#[derive(Clone)]
+struct SaveOptions {
+    output_dir: PathBuf,
+    output: OutputOptions,
+    crop: Option<CropOptions>,
+    keep_raw: bool,
+    name_template: NameTemplate,
+    json: bool,
+    timings: bool,
+    manifest: Option<PathBuf>,
+    stdout: bool,
+    #[cfg(feature = "clipboard")]
+    clipboard: bool,
+    #[cfg(feature = "ocr")]
+    ocr_lang: Option<String>,
+    #[cfg(feature = "s3")]
+    s3: Option<resnap_rs::S3Target>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<resnap_rs::WebhookOptions>,
+    debug_contours: Option<PathBuf>,
+    svg: Option<(PathBuf, SvgOptions)>,
+    split_gap: Option<u32>,
+    /// PDF collecting a page per capture, shared by all captures of the run
+    pdf: Option<(PathBuf, Arc<Mutex<PdfDocument>>)>,
+    /// Animation getting a frame per capture, created with the first frame and taken out to be
+    /// finished when watching stops
+    animation: Option<(PathBuf, Duration, Arc<Mutex<Option<GifAnimation>>>)>,
+    /// Device added to file names when capturing from several tablets
+    device: Option<String>,
+    dry_run: bool,
+    encode_crop_only: bool,
+    keep: Keep,
+    force: bool,
+    allow_empty: bool,
+    battery_warn: u8,
+}
+
+impl SaveOptions {
+    /// Options for one of several tablets, with its address added to every file name
+    fn for_device(&self, ip_address: &str) -> Result<Self, ResnapError> {
+        Ok(Self {
+            name_template: NameTemplate::new(&format!(
+                "{}-{}",
+                self.name_template.as_str(),
+                ip_address
+            ))?,
+            device: Some(ip_address.to_string()),
+            ..self.clone()
+        })
+    }
+}
+
+/// Images saved of each capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Keep {
+    /// Only the full screen image
+    Full,
+    /// Only the cropped image, or the full screen when there is none
+    Crop,
+    /// The full screen and the cropped image
+    Both,
+}
+
+/// Host key checking policies, mirroring `openssh::KnownHosts`
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum KnownHostsPolicy {
+    /// Add unknown host keys, reject changed ones
+    Add,
+    /// Only accept host keys already in the known hosts file
+    Strict,
+    /// Accept any host key
+    Accept,
+}
+
+impl From<KnownHostsPolicy> for KnownHosts {
+    fn from(policy: KnownHostsPolicy) -> Self {
+        match policy {
+            KnownHostsPolicy::Add => KnownHosts::Add,
+            KnownHostsPolicy::Strict => KnownHosts::Strict,
+            KnownHostsPolicy::Accept => KnownHosts::Accept,
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+async fn main() -> ExitCode {
+    match try_main().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            print_error(e.as_ref());
+            let status = e
+                .downcast_ref::<ResnapError>()
+                .map_or(EXIT_FAILURE, exit_status);
+            ExitCode::from(status)
+        }
+    }
+}
+
+/// Print an error to stderr, followed by the errors that caused it
+fn print_error(e: &dyn std::error::Error) {
+    eprintln!("Error: {}", e);
+
+    // Most errors already include their cause's message, so only the rest is added
+    let mut printed = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        if !printed.contains(&message) {
+            eprintln!("  Caused by: {}", message);
+            printed.push_str(&message);
+        }
+        source = cause.source();
+    }
+}
+
+/// Exit status reporting `e`
+fn exit_status(e: &ResnapError) -> u8 {
+    match e {
+        ResnapError::Ssh(_)
+        | ResnapError::Timeout(_)
+        | ResnapError::IdentityNotFound(_)
+        | ResnapError::Mdns(_)
+        | ResnapError::ShortRead { .. }
+        | ResnapError::Sftp(_) => EXIT_CONNECTION,
+        ResnapError::ProcessNotFound(_) => EXIT_NO_XOCHITL,
+        ResnapError::FramebufferMappingMissing(_) | ResnapError::InvalidAddress(_) => {
+            EXIT_NO_MAPPING
+        }
+        ResnapError::FfmpegNotInstalled | ResnapError::Ffmpeg(_) | ResnapError::Decode(_) => {
+            EXIT_DECODE
+        }
+        ResnapError::NoContent => EXIT_NO_CONTENT,
+        _ => EXIT_FAILURE,
+    }
+}
+
+async fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    // Parse command-line arguments, with defaults from the config file
+    let cli = parse_args()?;
+    let (args, info) = match cli.command {
+        None => (cli.args, false),
+        Some(Command::Capture(args)) => (args, false),
+        Some(Command::Reprocess { raw, mut args }) => {
+            args.from_raw = Some(raw);
+            (args, false)
+        }
+        Some(Command::Info(args)) => (args, true),
+    };
+
+    let mut logger = env_logger::Builder::from_default_env();
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => Some(log::LevelFilter::Error),
+        (false, 0) if args.dry_run => {
+            // Show the discovered PID, address and bounding box
+            Some(log::LevelFilter::Info)
+        }
+        // Leave the level to RUST_LOG
+        (false, 0) => None,
+        (false, 1) => Some(log::LevelFilter::Info),
+        (false, 2) => Some(log::LevelFilter::Debug),
+        (false, _) => Some(log::LevelFilter::Trace),
+    };
+    if let Some(level) = level {
+        logger.filter_level(level);
+    }
+    if args.log_format == LogFormat::Json {
+        logger.format(|buf, record| {
+            writeln!(buf, "{}", resnap_rs::json_log_record(record, Utc::now()))
+        });
+    }
+    logger.init();
 
-    // Parse command-line arguments
-    let args = Args::parse();
-    let remarkable_ip = args.ip_address;
     let output_dir = args.output_dir;
 
     // Ensure output directory exists
-    if !output_dir.exists() {
+    if !output_dir.exists() && !args.dry_run && !args.stdout && !info {
         fs::create_dir_all(&output_dir)?;
     }
 
-    let session = Session::connect(
-        format!("ssh://root@{}", remarkable_ip),
-        openssh::KnownHosts::Add,
-    )
-    .await?;
+    if args.dry_run && args.wake {
+        log::info!("🧪 Dry run, not waking the display");
+    }
+
+    let output = OutputOptions {
+        format: args.format,
+        quality: args.quality,
+        png_compression: args.png_compression,
+        invert: args.invert_output,
+        dpi: args.dpi,
+    };
+
+    let decode = DecodeOptions {
+        decoder: args.decoder,
+        black_point: args.black_point,
+        white_point: args.white_point,
+        no_curves: args.no_curves,
+        rotation: args.rotate,
+        filters: args.vf,
+        replace_filters: args.vf_replace,
+    };
+    decode.validate()?;
 
-    log::info!("✅ Connected to reMarkable at {}", remarkable_ip);
-
-    // Find `xochitl` process ID
-    let pid_output = session
-        .command("/bin/pidof")
-        .arg("xochitl")
-        .output()
-        .await?;
-    let mut pid = String::from_utf8_lossy(&pid_output.stdout)
-        .split_whitespace()
-        .next()
-        .ok_or("Could not find xochitl process ID")?
-        .to_string();
-    log::info!("🆔 Found xochitl PID: {}", pid);
-
-    // Find framebuffer memory address
-    // First check if this process has the right mapping
-    let maps_check = session
-        .command("grep")
-        .args(["-C1", "/dev/fb0", &format!("/proc/{}/maps", pid)])
-        .output()
-        .await?;
-
-    if maps_check.stdout.is_empty() {
-        // If the first PID doesn't have the right mapping, find one that does
-        let pids_output = String::from_utf8_lossy(&pid_output.stdout);
-        let all_pids = pids_output.split_whitespace().collect::<Vec<&str>>();
-
-        let mut found_pid = None;
-        for test_pid in all_pids {
-            let check = session
-                .command("grep")
-                .args(["-C1", "/dev/fb0", &format!("/proc/{}/maps", test_pid)])
-                .output()
-                .await?;
-
-            if !check.stdout.is_empty() {
-                found_pid = Some(test_pid.to_string());
-                break;
+    let crop = (!args.no_crop).then_some(CropOptions {
+        threshold: args.threshold,
+        threshold_mode: args.threshold_mode,
+        gamma: args.gamma,
+        auto_contrast: args.auto_contrast.then_some(args.auto_contrast_clip),
+        denoise: args.denoise,
+        min_contour_size: args.min_contour_size,
+        visualize_contours: args.debug_contours.is_some(),
+        mask_ui: args.mask_ui,
+        ui_exclude: (!args.no_exclude).then_some((args.exclude_x, args.exclude_y)),
+        padding: args.padding,
+        trim: args.trim.then_some(args.trim_tolerance),
+        fixed_rect: args.fixed_crop.map(|rect| rect.parse()).transpose()?,
+        region: args.region.map(|rect| rect.parse()).transpose()?,
+        invert: args.invert,
+    });
+    if let Some(crop) = &crop {
+        crop.validate()?;
+    }
+
+    let save = SaveOptions {
+        output_dir,
+        output,
+        crop,
+        keep_raw: args.keep_raw,
+        name_template: NameTemplate::new(&args.name_template)?,
+        json: args.json,
+        timings: args.timings,
+        manifest: args.manifest,
+        stdout: args.stdout,
+        #[cfg(feature = "clipboard")]
+        clipboard: args.clipboard,
+        #[cfg(feature = "ocr")]
+        ocr_lang: args.ocr.then_some(args.ocr_lang),
+        #[cfg(feature = "s3")]
+        s3: args.s3.map(|target| target.parse()).transpose()?,
+        #[cfg(feature = "webhook")]
+        webhook: args
+            .webhook
+            .map(|url| resnap_rs::WebhookOptions::new(&url, &args.webhook_header))
+            .transpose()?,
+        debug_contours: args.debug_contours,
+        svg: args.svg.map(|path| {
+            let opts = SvgOptions {
+                stroke: args.svg_stroke,
+                stroke_width: args.svg_stroke_width,
+            };
+            (path, opts)
+        }),
+        split_gap: args.split.then_some(args.split_gap),
+        pdf: args
+            .pdf
+            .filter(|_| !args.dry_run)
+            .map(|path| (path, Arc::new(Mutex::new(PdfDocument::new())))),
+        animation: match args.animate.filter(|_| !args.dry_run) {
+            Some(path) => {
+                GifAnimation::check_path(&path)?;
+                // Checked again before the first frame creates the file, like the images
+                if !args.force && path.exists() {
+                    return Err(ResnapError::OutputExists(path).into());
+                }
+                let delay = Duration::from_millis(args.animate_delay);
+                Some((path, delay, Arc::new(Mutex::new(None))))
             }
-        }
+            None => None,
+        },
+        device: None,
+        dry_run: args.dry_run,
+        encode_crop_only: args.encode_crop_only,
+        keep: args.keep,
+        force: args.force,
+        allow_empty: args.allow_empty,
+        battery_warn: args.battery_warn,
+    };
+
+    if let Some(raw_path) = args.from_raw {
+        let model = args.model.unwrap_or_default();
+        let img = resnap_rs::decode_raw_file(&raw_path, model, &decode)?;
+        let capture = Capture::from_screen(img, model, save.crop.as_ref())?;
+        save_capture(&capture, &save, &Utc::now(), None).await?;
+        save_pdf(&save)?;
+        return Ok(require_content(&capture, &save)?);
+    }
+
+    // A local capture is labelled like a tablet named localhost
+    let mut ip_addresses = if args.local {
+        vec!["localhost".to_string()]
+    } else {
+        args.ip_address
+    };
+    if ip_addresses.is_empty() {
+        return Err(ResnapError::InvalidConfig(
+            "no tablet address; pass --ip-address, set RESNAP_IP or capture with --local"
+                .to_string(),
+        )
+        .into());
+    }
+    for ip_address in &ip_addresses {
+        resnap_rs::validate_host(ip_address)?;
+    }
+    let config = CaptureConfig {
+        ip_address: ip_addresses.remove(0),
+        user: args.user,
+        port: args.port,
+        ssh_config: args.ssh_config,
+        known_hosts: args.known_hosts.into(),
+        timeout: (args.timeout > 0).then(|| Duration::from_secs(args.timeout)),
+        retry: RetryPolicy {
+            retries: args.retries,
+            delay: Duration::from_millis(args.retry_delay),
+        },
+        identity: args.identity,
+        jump: args.jump,
+        model: args.model,
+        wake: args.wake && !args.dry_run,
+        backend: args.backend,
+        framebuffer: args.fb,
+        transfer: args.transfer,
+        chunk_size: args.chunk_size,
+        compress: args.compress,
+        decode,
+        crop: save.crop.clone(),
+        keep_raw: None,
+    };
+
+    if info {
+        ip_addresses.insert(0, config.ip_address.clone());
+        return print_info(&config, &ip_addresses, save.json, args.local).await;
+    }
+
+    let watch = args.watch.map(|interval| WatchOptions {
+        interval,
+        // Frames of an animation must all have the same size
+        lock_crop: args.lock_crop
+            || (save.animation.is_some() && args.fixed_crop.is_none() && args.region.is_none()),
+        battery_stop: args.battery_stop,
+        skip_unchanged: args.skip_unchanged,
+    });
+    let result = run(&config, &save, ip_addresses, watch, args.local).await;
+
+    // Keep the pages and frames captured before a failure
+    let pdf = save_pdf(&save);
+    finish_animation(&save);
+    result?;
+    pdf
+}
+
+/// Capture once from each tablet, or keep capturing from a single one with `watch`
+///
+/// With `local`, the machine resnap runs on is captured instead of connecting over SSH.
+async fn run(
+    config: &CaptureConfig,
+    save: &SaveOptions,
+    mut ip_addresses: Vec<String>,
+    watch: Option<WatchOptions>,
+    local: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if local {
+        return match watch {
+            Some(watch) => watch_device(&LocalExec, config, save, watch).await,
+            None => {
+                let mut cache = CaptureCache::default();
+                let (capture, _) =
+                    capture_and_save(&LocalExec, config, save, &mut cache, None).await?;
+                Ok(require_content(&capture, save)?)
+            }
+        };
+    }
 
-        if let Some(p) = found_pid {
-            log::info!("🔄 Switching to PID {} which has fb0 mapping", p);
-            pid.clear();
-            pid.push_str(&p);
-        } else {
-            return Err("Could not find any xochitl process with /dev/fb0 mapping".into());
+    if !ip_addresses.is_empty() {
+        if watch.is_some() || save.stdout {
+            let flag = if save.stdout { "--stdout" } else { "--watch" };
+            return Err(
+                ResnapError::InvalidConfig(format!("{} supports a single tablet", flag)).into(),
+            );
         }
+        ip_addresses.insert(0, config.ip_address.clone());
+        return capture_devices(config, save, &ip_addresses).await;
     }
 
-    // Get the address after /dev/fb0 mapping
-    let address_cmd = format!(
-        "grep -C1 '/dev/fb0' /proc/{}/maps | tail -n1 | sed 's/-.*$//'",
-        pid
-    );
+    let Some(watch) = watch else {
+        let time = Utc::now();
+        let config = with_keep_raw(config, save, &time);
+        let progress = CaptureProgress::new(save);
+        let report = |stage: Stage| progress.report(stage);
+        let capture = config
+            .retry
+            .run(|| resnap_rs::connect_and_capture_with_progress(&config, &report))
+            .await?;
+        let timings = progress.timings();
+        drop(progress);
+        save_capture(&capture, save, &time, timings.as_ref()).await?;
+        return Ok(require_content(&capture, save)?);
+    };
 
-    let address_output = session
-        .command("sh")
-        .arg("-c")
-        .arg(&address_cmd)
-        .output()
-        .await?;
-
-    let skip_bytes_hex = String::from_utf8_lossy(&address_output.stdout)
-        .trim()
-        .to_string();
-    let skip_bytes = u64::from_str_radix(&skip_bytes_hex, 16)? + 7;
-    log::info!(
-        "📍 Found framebuffer at address: 0x{} + 7 = {}",
-        skip_bytes_hex,
-        skip_bytes
-    );
+    let session = config.retry.run(|| resnap_rs::connect(config)).await?;
+    watch_device(&session, config, save, watch).await?;
+    session.close().await?;
 
-    // Calculate window size
-    let width = 1872;
-    let height = 1404;
+    Ok(())
+}
 
-    let (bytes_per_pixel, pixel_format, transpose) = (2, "gray16", "transpose=2,hflip"); // 90° clockwise and horizontal flip
+/// Capture every `watch.interval` seconds over an open session until Ctrl-C
+///
+/// With `lock_crop`, the first bounding box found becomes the fixed crop of every later
+/// capture. With `skip_unchanged`, captures identical to the one before aren't saved, and
+/// their number is printed at the end. Watching stops once the battery drops below
+/// `battery_stop`.
+async fn watch_device(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    save: &SaveOptions,
+    watch: WatchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cache = CaptureCache::default();
+    let mut config = config.clone();
+    let mut save = save.clone();
+    let mut last_screen = watch.skip_unchanged.then_some(None);
+    let mut skipped = 0;
 
-    let window_bytes = width * height * bytes_per_pixel;
     log::info!(
-        "📏 Window size: {}x{} ({}B per pixel, {} total)",
-        width,
-        height,
-        bytes_per_pixel,
-        window_bytes
+        "👀 Capturing every {}s, press Ctrl-C to stop",
+        watch.interval
     );
+    let mut ticker = tokio::time::interval(Duration::from_secs(watch.interval));
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = ticker.tick() => {}
+        }
 
-    // Create command to extract framebuffer data
-    let dd_cmd = format!(
-        "{{ dd bs=1 skip={} count=0 && dd bs={} count=1; }} < /proc/{}/mem 2>/dev/null",
-        skip_bytes, window_bytes, pid
-    );
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            result = capture_and_save(session, &config, &save, &mut cache, last_screen.as_mut()) => {
+                let capture = match result {
+                    Ok((capture, true)) => capture,
+                    Ok((capture, false)) => {
+                        skipped += 1;
+                        log::info!("⏭️ Screen unchanged, not saved ({} skipped)", skipped);
+                        capture
+                    }
+                    // Keep watching through transient failures
+                    Err(e) => {
+                        log::error!("❌ Capture failed: {}", e);
+                        continue;
+                    }
+                };
 
-    log::info!("📤 Extracting framebuffer data...");
-    let fb_data = session
-        .command("sh")
-        .arg("-c")
-        .arg(&dd_cmd)
-        .output()
-        .await?;
-
-    // Save raw data to temp file in the output directory
-    let temp_file = output_dir.join("remarkable_fb.raw");
-    let mut file = File::create(&temp_file)?;
-    file.write_all(&fb_data.stdout)?;
-    log::info!("💾 Saved raw framebuffer to {}", temp_file.display());
-
-    // Build ffmpeg filter chain
-    let mut filters = String::from(transpose);
-    filters.push_str(",curves=all=0.045/0 0.06/1");
-
-    // Convert raw framebuffer to image using ffmpeg
-    let now = Utc::now();
-    let formatted_datetime = format!("{}-remarkable-screen.png", now.format("%m-%d-%Y-%H-%M-%S"));
-    let output_file = output_dir.join(&formatted_datetime);
-    let status = Command::new("ffmpeg")
-        .args([
-            "-f",
-            "rawvideo",
-            "-pixel_format",
-            pixel_format,
-            "-video_size",
-            &format!("{}x{}", width, height),
-            "-i",
-            &temp_file.to_string_lossy(),
-            "-vf",
-            &filters,
-            "-y",
-            &output_file.to_string_lossy(),
-        ])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()?;
-
-    if status.success() {
-        log::info!(
-            "🖼️ Converted framebuffer to image: {}",
-            output_file.display()
-        );
-        // Clean up temporary file
-        fs::remove_file(&temp_file)?;
+                let unlocked = config.crop.as_ref().is_some_and(|crop| crop.fixed_rect.is_none());
+                let detected = capture.detection.as_ref().and_then(|d| d.bounding_box);
+                if let Some(rect) = detected.filter(|_| watch.lock_crop && unlocked) {
+                    log::info!(
+                        "🔒 Locked crop to {},{},{},{}",
+                        rect.x, rect.y, rect.width, rect.height
+                    );
+                    for crop in [&mut config.crop, &mut save.crop].into_iter().flatten() {
+                        crop.fixed_rect = Some(rect);
+                    }
+                }
+
+                if let (Some(battery), Some(stop)) = (capture.battery, watch.battery_stop) {
+                    if battery < stop {
+                        log::warn!("🪫 Battery at {}%, below {}%; stopping", battery, stop);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    log::info!("👋 Stopped watching");
+    if watch.skip_unchanged {
+        eprintln!("⏭️ Skipped {} unchanged captures", skipped);
+    }
+
+    Ok(())
+}
+
+/// Connect to each tablet in turn, or look at this machine with `local`, and print what
+/// [`resnap_rs::device_info`] finds out
+async fn print_info(
+    config: &CaptureConfig,
+    ip_addresses: &[String],
+    json: bool,
+    local: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if local {
+        let info = resnap_rs::device_info(&LocalExec, config).await?;
+        print_device_info(&config.ip_address, &info, json);
+        return Ok(());
+    }
+
+    for ip_address in ip_addresses {
+        let config = CaptureConfig {
+            ip_address: ip_address.clone(),
+            ..config.clone()
+        };
+        let session = config.retry.run(|| resnap_rs::connect(&config)).await?;
+        let info = resnap_rs::device_info(&session, &config).await?;
+        session.close().await?;
+
+        print_device_info(ip_address, &info, json);
+    }
+
+    Ok(())
+}
+
+/// Print the details of one tablet, as JSON or for reading
+fn print_device_info(ip_address: &str, info: &DeviceInfo, json: bool) {
+    if json {
+        println!("{}", info.to_json());
     } else {
-        return Err("Failed to convert framebuffer to image".into());
+        let battery = info
+            .battery
+            .map(|battery| format!("{}%", battery))
+            .unwrap_or_else(|| "unknown".to_string());
+        let mapping = match &info.location {
+            Ok(location) => format!("found in xochitl (PID {})", location.pid),
+            Err(e) => format!("not found: {}", e),
+        };
+        println!("{}", ip_address);
+        println!("  Model:        {}", info.model);
+        println!(
+            "  Firmware:     {}",
+            info.firmware.as_deref().unwrap_or("unknown")
+        );
+        println!(
+            "  Framebuffer:  {}x{} {} ({} bytes per pixel) at {} dpi",
+            info.framebuffer.width,
+            info.framebuffer.height,
+            info.framebuffer.pixel_format,
+            info.framebuffer.bytes_per_pixel,
+            info.model.dpi()
+        );
+        println!("  Battery:      {}", battery);
+        println!("  {:<14}{}", format!("fb{} mapping:", info.fb), mapping);
     }
+}
 
-    let img = image::open(&output_file)?;
+/// Write the pages collected with `--pdf`, if any
+fn save_pdf(save: &SaveOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((path, pdf)) = &save.pdf else {
+        return Ok(());
+    };
+
+    let pdf = pdf.lock().expect("PDF lock poisoned");
+    if pdf.is_empty() {
+        log::warn!("⚠️ No captures to save as PDF");
+        return Ok(());
+    }
+    pdf.save(path)?;
+    log::info!("📄 Saved {} pages as PDF: {}", pdf.len(), path.display());
 
-    // Convert to grayscale if not already
-    let gray_img = img.to_luma8();
+    Ok(())
+}
 
-    // Set threshold to isolate handwriting (assuming dark writing on light background)
-    let threshold = 200; // Adjust as needed for your images
+/// Finish the animation started with `--animate`, if any
+fn finish_animation(save: &SaveOptions) {
+    let Some((path, _, animation)) = &save.animation else {
+        return;
+    };
 
-    // Define UI exclusion zone (menu button in top-left)
-    let ui_exclude_x = 200; // Exclude x < this value
-    let ui_exclude_y = 200; // Exclude y < this value
-                            // Create a binary image to isolate the handwriting
-    let binary_img = ImageBuffer::from_fn(gray_img.width(), gray_img.height(), |x, y| {
-        if x < ui_exclude_x && y < ui_exclude_y {
-            return Luma([255]); // Mark as background
+    match animation.lock().expect("animation lock poisoned").take() {
+        Some(animation) => {
+            let frames = animation.frames();
+            animation.finish();
+            log::info!(
+                "🎞️ Saved {} frames as animation: {}",
+                frames,
+                path.display()
+            );
         }
-        let pixel = gray_img.get_pixel(x, y).0[0];
-        if pixel < threshold {
-            Luma([0]) // Black - this is handwriting
-        } else {
-            Luma([255]) // White - this is background
+        None => log::warn!("⚠️ No captures to animate: {}", path.display()),
+    }
+}
+
+/// Parse the command line, filling in options it doesn't give from the config file
+fn parse_args() -> Result<Cli, Box<dyn std::error::Error>> {
+    let cli: Vec<OsString> = env::args_os().collect();
+
+    // A first lenient pass finds --config and which options the command line sets
+    let root = Cli::command();
+    let root_matches = root.clone().ignore_errors(true).get_matches_from(&cli);
+
+    // Options follow the subcommand when one is given, which then comes first
+    let (cmd, matches, options_start) = match root_matches.subcommand() {
+        Some((name, matches)) => {
+            let cmd = root
+                .find_subcommand(name)
+                .expect("matched subcommand exists");
+            (cmd, matches, 2)
         }
-    });
+        None => (&root, &root_matches, 1),
+    };
+    let explicit = matches.get_one::<PathBuf>("config");
 
-    // Find contours in the binary image
-    let contours = contours::find_contours::<i32>(&binary_img);
+    let Some(path) = resnap_rs::find_config_file(explicit.map(PathBuf::as_path)) else {
+        return Ok(Cli::parse_from(cli));
+    };
+    let file_args = resnap_rs::config_file_args(&path, cmd, matches)?;
 
-    // Create visualization of contours for debugging
-    let mut contour_vis = RgbaImage::new(gray_img.width(), gray_img.height());
-    // Fill with white background
-    for pixel in contour_vis.pixels_mut() {
-        *pixel = Rgba([255, 255, 255, 255]);
-    }
+    // Later occurrences win, so the command line overrides the file
+    let args = cli
+        .iter()
+        .take(options_start)
+        .chain(&file_args)
+        .chain(cli.iter().skip(options_start));
 
-    // Calculate bounding box for all content of interest
-    let mut min_x = gray_img.width();
-    let mut min_y = gray_img.height();
-    let mut max_x = 150;
-    let mut max_y = 0;
+    Ok(Cli::parse_from(args))
+}
 
-    let mut found_contours = 0;
-    let mut large_contours = 0;
+/// Progress of one capture, logged for `--verbose`, shown as a bar on a terminal and timed for
+/// `--timings`
+struct CaptureProgress {
+    #[cfg(feature = "progress")]
+    bar: Option<ProgressBar>,
+    timings: Option<Mutex<StageTimings>>,
+}
 
-    // Filter out small noise contours
-    let min_contour_size = 100; // Adjust this threshold as needed
+impl CaptureProgress {
+    /// Start reporting a capture, without a bar under `--json` or `--stdout` or when stdout isn't
+    /// a terminal
+    fn new(save: &SaveOptions) -> Self {
+        Self {
+            #[cfg(feature = "progress")]
+            bar: (!save.json && !save.stdout && std::io::stdout().is_terminal()).then(|| {
+                let bar = ProgressBar::new_spinner();
+                bar.enable_steady_tick(Duration::from_millis(100));
+                bar
+            }),
+            timings: save.timings.then(|| Mutex::new(StageTimings::default())),
+        }
+    }
 
-    for contour in contours {
-        found_contours += 1;
+    /// Time spent in each stage so far, with `--timings`
+    fn timings(&self) -> Option<StageTimings> {
+        self.timings.as_ref().map(|timings| {
+            let mut timings = timings.lock().expect("timings lock poisoned").clone();
+            timings.finish();
+            timings
+        })
+    }
 
-        // Skip very small contours (likely noise)
-        if contour.points.len() < min_contour_size {
-            continue;
+    /// Report that the capture reached `stage`
+    fn report(&self, stage: Stage) {
+        if !matches!(stage, Stage::Transferring { .. }) {
+            log::debug!("⏳ {:?}", stage);
         }
 
-        large_contours += 1;
+        if let Some(timings) = &self.timings {
+            timings.lock().expect("timings lock poisoned").record(stage);
+        }
 
-        // Draw contour for visualization
-        for point in &contour.points {
-            if point.x >= 0
-                && point.y >= 0
-                && point.x < gray_img.width() as i32
-                && point.y < gray_img.height() as i32
-            {
-                contour_vis.put_pixel(point.x as u32, point.y as u32, Rgba([255, 0, 0, 255]));
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            let spinner =
+                ProgressStyle::with_template("{spinner} {msg}").expect("valid progress template");
+            match stage {
+                Stage::Connecting => bar.set_message("Connecting"),
+                Stage::ResolvingPid => bar.set_message("Locating framebuffer"),
+                Stage::ReadingFramebuffer { bytes } => {
+                    bar.set_style(
+                        ProgressStyle::with_template(
+                            "{spinner} {msg} [{bar:30}] {bytes}/{total_bytes}",
+                        )
+                        .expect("valid progress template")
+                        .progress_chars("=> "),
+                    );
+                    bar.set_length(bytes as u64);
+                    bar.set_position(0);
+                    bar.set_message("Reading framebuffer");
+                }
+                Stage::Transferring { read, .. } => bar.set_position(read as u64),
+                Stage::Decoding => {
+                    bar.set_style(spinner);
+                    bar.set_message("Decoding");
+                }
+                Stage::DetectingContours => bar.set_message("Detecting contours"),
+                Stage::Cropping => bar.set_message("Cropping"),
+                Stage::Done => bar.finish_and_clear(),
             }
         }
+    }
+}
 
-        // Update bounding box
-        for point in &contour.points {
-            if point.x >= 0
-                && point.y >= 0
-                && point.x < gray_img.width() as i32
-                && point.y < gray_img.height() as i32
-            {
-                min_x = min_x.min(point.x as u32);
-                min_y = min_y.min(point.y as u32);
-                max_x = max_x.max(point.x as u32);
-                max_y = max_y.max(point.y as u32);
+#[cfg(feature = "progress")]
+impl Drop for CaptureProgress {
+    fn drop(&mut self) {
+        // Clear the bar of a capture that failed or was interrupted
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Capture from several tablets concurrently and print a summary of each to stderr
+///
+/// A failure on one tablet doesn't stop the others, but makes the whole run fail.
+async fn capture_devices(
+    config: &CaptureConfig,
+    save: &SaveOptions,
+    ip_addresses: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let time = Utc::now();
+
+    let results = join_all(ip_addresses.iter().map(|ip_address| async move {
+        let save = save.for_device(ip_address)?;
+        let mut config = with_keep_raw(config, &save, &time);
+        config.ip_address = ip_address.clone();
+
+        let capture = config
+            .retry
+            .run(|| resnap_rs::connect_and_capture(&config))
+            .await?;
+        save_capture(&capture, &save, &time, None).await?;
+        Ok(require_content(&capture, &save)?)
+    }))
+    .await;
+
+    let mut failed = 0;
+    for (ip_address, result) in ip_addresses.iter().zip(&results) {
+        match result {
+            Ok(()) => eprintln!("✅ {}: captured", ip_address),
+            Err(e) => {
+                failed += 1;
+                eprintln!("❌ {}: {}", ip_address, e);
             }
         }
     }
 
-    log::info!(
-        "Found {} contours, {} significant",
-        found_contours,
-        large_contours
-    );
+    if failed > 0 {
+        return Err(format!("{} of {} captures failed", failed, ip_addresses.len()).into());
+    }
+
+    Ok(())
+}
+
+/// Configuration for one capture, keeping the raw dump under its timestamp if requested
+fn with_keep_raw(
+    config: &CaptureConfig,
+    save: &SaveOptions,
+    time: &DateTime<Utc>,
+) -> CaptureConfig {
+    let mut config = config.clone();
+    config.keep_raw = (save.keep_raw && !save.dry_run).then(|| {
+        let device = save
+            .device
+            .as_ref()
+            .map(|device| format!("-{}", device))
+            .unwrap_or_default();
+        save.output_dir.join(format!(
+            "{}{}-remarkable-fb.raw",
+            time.format("%m-%d-%Y-%H-%M-%S"),
+            device
+        ))
+    });
+    config
+}
+
+/// Capture the screen over an open session, save the results and return the capture
+///
+/// With `last_screen`, the screen is hashed and compared with the hash kept there from the
+/// previous capture, and an identical capture is not saved. Whether the capture was saved is
+/// returned along with it.
+async fn capture_and_save(
+    session: &impl RemoteExec,
+    config: &CaptureConfig,
+    save: &SaveOptions,
+    cache: &mut CaptureCache,
+    last_screen: Option<&mut Option<u64>>,
+) -> Result<(Capture, bool), Box<dyn std::error::Error>> {
+    let time = Utc::now();
+    let config = with_keep_raw(config, save, &time);
+
+    let progress = CaptureProgress::new(save);
+    let capture = resnap_rs::capture_once_cached_with_progress(session, &config, cache, &|stage| {
+        progress.report(stage)
+    })
+    .await?;
+    let timings = progress.timings();
+    drop(progress);
+
+    if let Some(last_screen) = last_screen {
+        let mut hasher = DefaultHasher::new();
+        capture.screen.as_bytes().hash(&mut hasher);
+        let hash = hasher.finish();
+        if last_screen.replace(hash) == Some(hash) {
+            return Ok((capture, false));
+        }
+    }
+
+    save_capture(&capture, save, &time, timings.as_ref()).await?;
+
+    Ok((capture, true))
+}
+
+/// Fail with [`ResnapError::NoContent`] when cropping found nothing, unless `--allow-empty`
+/// was given
+///
+/// Watching doesn't check, since empty pages are expected while flipping through a notebook.
+fn require_content(capture: &Capture, save: &SaveOptions) -> Result<(), ResnapError> {
+    if save.crop.is_some() && capture.cropped.is_none() && !save.allow_empty {
+        return Err(ResnapError::NoContent);
+    }
+    Ok(())
+}
+
+/// Hash a saved image and append it to the `--manifest`, returning its SHA-256 hash; `None`
+/// without a manifest
+fn record_in_manifest(save: &SaveOptions, path: &Path) -> Result<Option<String>, ResnapError> {
+    let Some(manifest) = &save.manifest else {
+        return Ok(None);
+    };
 
-    // Add padding to the bounding box
-    let padding = 50;
-    let min_x = min_x.saturating_sub(padding);
-    let min_y = min_y.saturating_sub(padding);
-    let max_x = (max_x + padding).min(gray_img.width() - 1);
-    let max_y = (max_y + padding).min(gray_img.height() - 1);
-
-    // If we found a valid bounding box (content of interest)
-    if min_x < max_x && min_y < max_y && large_contours > 0 {
-        // Crop to the bounding box region
-        let width = max_x - min_x + 1;
-        let height = max_y - min_y + 1;
-
-        log::info!(
-            "📏 Content bounding box: ({}, {}) to ({}, {}), size: {}x{}",
-            min_x,
-            min_y,
-            max_x,
-            max_y,
-            width,
-            height
+    let digest = FileDigest::of_file(path)?;
+    resnap_rs::append_to_manifest(manifest, path, &digest)?;
+    log::debug!("🔏 {} has SHA-256 {}", path.display(), digest.sha256);
+
+    Ok(Some(digest.sha256))
+}
+
+/// Save the full screen image and, unless cropping is disabled, the cropped content
+///
+/// Prints the path of the cropped image, or of the full image when not cropping, or a JSON
+/// description of the capture with `--json`. The URL of the uploaded object follows the path
+/// with `--s3`. With `--stdout`, only the image is written, to stdout. `timings` are printed to
+/// stderr and included in the JSON.
+async fn save_capture(
+    capture: &Capture,
+    save: &SaveOptions,
+    time: &DateTime<Utc>,
+    timings: Option<&StageTimings>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // With --allow-empty a page without content is kept whole in place of the crop
+    let whole;
+    let capture = if save.allow_empty && save.crop.is_some() && capture.cropped.is_none() {
+        whole = Capture {
+            cropped: Some(capture.screen.clone()),
+            ..capture.clone()
+        };
+        &whole
+    } else {
+        capture
+    };
+
+    let output_dir = &save.output_dir;
+    let output = &OutputOptions {
+        dpi: Some(save.output.dpi.unwrap_or(capture.model.dpi())),
+        ..save.output.clone()
+    };
+    let extension = output.format.extension();
+
+    if let Some(battery) = capture
+        .battery
+        .filter(|&battery| battery < save.battery_warn)
+    {
+        log::warn!("🪫 Tablet battery is low: {}%", battery);
+    }
+
+    if let Some(timings) = timings {
+        let stages: Vec<String> = timings
+            .stages
+            .iter()
+            .map(|(name, duration)| format!("{} {}ms", name, duration.as_millis()))
+            .collect();
+        eprintln!(
+            "⏱️ {} (total {}ms)",
+            stages.join(", "),
+            timings.total().as_millis()
         );
+    }
 
-        // Create cropped image
-        let cropped = img.crop_imm(min_x, min_y, width, height);
+    if save.stdout {
+        let img = match &save.crop {
+            None => &capture.screen,
+            Some(_) => capture.cropped.as_ref().ok_or(ResnapError::NoContent)?,
+        };
+        let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+        resnap_rs::write_image(img, &mut stdout, output)?;
+        return Ok(());
+    }
 
-        // Convert to RGBA to enable transparency
-        let mut rgba_img = RgbaImage::new(width, height);
+    // Pick the first unused counter value when the template has one
+    let mut n = 1;
+    let mut output_stem = save.name_template.render(time, capture.model, n);
+    while save.name_template.uses_counter()
+        && [
+            format!("{}.{}", output_stem, extension),
+            format!("{}_cropped.{}", output_stem, extension),
+        ]
+        .iter()
+        .any(|name| output_dir.join(name).exists())
+    {
+        n += 1;
+        output_stem = save.name_template.render(time, capture.model, n);
+    }
 
-        // Brightness threshold for transparency - adjust as needed
-        let transparency_threshold = 230;
+    // With --keep crop the full screen is only saved when there's no crop to keep instead
+    let keep_full = save.keep != Keep::Crop || capture.cropped.is_none();
+    if save.keep == Keep::Crop && capture.cropped.is_none() {
+        log::warn!("⚠️ No content found, keeping the full screen image");
+    }
+    let full_path = keep_full.then(|| output_dir.join(format!("{}.{}", output_stem, extension)));
+    let cropped_path = capture
+        .cropped
+        .as_ref()
+        .filter(|_| save.keep != Keep::Full)
+        .map(|_| output_dir.join(format!("{}_cropped.{}", output_stem, extension)));
 
-        // Copy pixels making the brightest ones transparent
-        for (x, y, pixel) in cropped.to_rgba8().enumerate_pixels() {
-            // Calculate brightness (average of RGB values as a simple approach)
-            let brightness = (pixel[0] as u16 + pixel[1] as u16 + pixel[2] as u16) / 3;
+    // The image handed on to OCR, uploads and webhooks: the crop when cropping, unless only
+    // the full screen is kept
+    let main_path = match &save.crop {
+        Some(_) if save.keep != Keep::Full => cropped_path.as_ref(),
+        _ => full_path.as_ref(),
+    };
 
-            if brightness >= transparency_threshold {
-                // Very bright pixel - make transparent
-                rgba_img.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], 0]));
-            } else {
-                // Keep original pixel with full opacity
-                rgba_img.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], 255]));
+    // The animation is created with its first frame, so an existing file is only in the way then
+    let new_animation = save
+        .animation
+        .as_ref()
+        .filter(|(_, _, animation)| animation.lock().expect("animation lock poisoned").is_none())
+        .map(|(path, ..)| path);
+
+    // Names without a counter or with only the time to the second can collide
+    if !save.force {
+        if let Some(path) = full_path
+            .iter()
+            .chain(&cropped_path)
+            .chain(new_animation)
+            .find(|path| path.exists())
+        {
+            return Err(ResnapError::OutputExists(path.clone()).into());
+        }
+    }
+
+    if save.dry_run {
+        if !save.json {
+            for path in full_path.iter().chain(&cropped_path) {
+                println!("Would save {}", path.display());
             }
         }
 
-        // Save cropped image with transparency
-        let output_stem = Path::new(&formatted_datetime)
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap();
-        let cropped_path = output_dir.join(format!("{}_cropped.png", output_stem));
-        rgba_img.save(&cropped_path)?;
-        log::info!(
-            "✅ Saved cropped content with transparency to: {}",
-            cropped_path.display()
+        let metadata = CaptureMetadata {
+            capture,
+            full_path: full_path.as_deref(),
+            cropped_path: cropped_path.as_deref(),
+            full_sha256: None,
+            cropped_sha256: None,
+            url: None,
+            ocr_text: None,
+            timings,
+            timestamp: *time,
+        };
+        println!("{}", metadata.to_json());
+        return Ok(());
+    }
+
+    // Save the full screen image
+    let full_sha256 = match &full_path {
+        Some(full_path) => {
+            let full_output = if save.encode_crop_only {
+                OutputOptions {
+                    quality: OutputOptions::default().quality,
+                    png_compression: PngCompression::default(),
+                    ..output.clone()
+                }
+            } else {
+                output.clone()
+            };
+            resnap_rs::save_image(&capture.screen, full_path, &full_output)?;
+            log::info!("🖼️ Saved screen image: {}", full_path.display());
+            record_in_manifest(save, full_path)?
+        }
+        None => None,
+    };
+
+    let contour_vis = capture
+        .detection
+        .as_ref()
+        .and_then(|d| d.contour_vis.as_ref());
+    if let (Some(path), Some(vis)) = (&save.debug_contours, contour_vis) {
+        vis.save(path)?;
+        log::info!("🔍 Saved contour visualization: {}", path.display());
+    }
+
+    if let (Some((path, opts)), Some(detection)) = (&save.svg, &capture.detection) {
+        // The fixed or locked crop, when there is one, rather than the detected content
+        if let Some(bounds) = capture.crop_rect {
+            let svg = resnap_rs::contours_to_svg(&detection.contours, bounds, opts);
+            fs::write(path, svg)?;
+            log::info!("✏️ Saved contours as SVG: {}", path.display());
+        }
+    }
+
+    if let (Some(gap), Some(detection), Some(crop)) =
+        (save.split_gap, &capture.detection, &save.crop)
+    {
+        let regions = resnap_rs::split_regions(
+            &detection.contours,
+            gap,
+            crop.padding,
+            capture.screen.width(),
+            capture.screen.height(),
         );
-        println!("{}", cropped_path.display());
+        for (i, rect) in regions.into_iter().enumerate() {
+            let region = resnap_rs::crop_to_rect(&capture.screen, rect, crop);
+            let region_path =
+                output_dir.join(format!("{}_region_{}.{}", output_stem, i + 1, extension));
+            resnap_rs::save_image(&region, &region_path, output)?;
+            log::info!("🧩 Saved region {}: {}", i + 1, region_path.display());
+        }
+    }
+
+    let cropped_sha256 = match capture.cropped.as_ref().zip(cropped_path.as_ref()) {
+        Some((cropped, cropped_path)) => {
+            // Save cropped image with transparency (where the format supports it)
+            resnap_rs::save_image(cropped, cropped_path, output)?;
+            log::info!(
+                "✅ Saved cropped content with transparency to: {}",
+                cropped_path.display()
+            );
+            record_in_manifest(save, cropped_path)?
+        }
+        None => None,
+    };
+
+    if let Some((_, pdf)) = &save.pdf {
+        let page = capture.cropped.as_ref().unwrap_or(&capture.screen);
+        let dpi = output.dpi.unwrap_or(capture.model.dpi());
+        pdf.lock().expect("PDF lock poisoned").add_page(page, dpi)?;
+    }
+
+    if let Some((path, delay, animation)) = &save.animation {
+        let frame = match &save.crop {
+            None => Some(&capture.screen),
+            Some(_) => capture.cropped.as_ref(),
+        };
+        if let Some(frame) = frame {
+            let mut animation = animation.lock().expect("animation lock poisoned");
+            if animation.is_none() {
+                *animation = Some(GifAnimation::create(path, *delay)?);
+            }
+            if let Some(animation) = animation.as_mut() {
+                animation.add_frame(frame)?;
+            }
+        }
+    }
+
+    #[cfg(feature = "clipboard")]
+    if save.clipboard {
+        if save.crop.is_none() {
+            resnap_rs::copy_to_clipboard(&capture.screen)?;
+        } else if let Some(cropped) = &capture.cropped {
+            resnap_rs::copy_to_clipboard(cropped)?;
+        }
+    }
+
+    #[allow(unused_mut)]
+    let mut ocr_text: Option<String> = None;
+    #[cfg(feature = "ocr")]
+    if let Some(lang) = &save.ocr_lang {
+        if let Some(ocr_path) = main_path {
+            let text = resnap_rs::recognize_text(ocr_path, lang)?;
+            let text_path = ocr_path.with_extension("txt");
+            fs::write(&text_path, &text)?;
+            log::info!("📝 Saved recognized text: {}", text_path.display());
+            ocr_text = Some(text);
+        }
+    }
+
+    #[allow(unused_mut)]
+    let mut url: Option<String> = None;
+    #[cfg(feature = "s3")]
+    if let Some(target) = &save.s3 {
+        if let Some(upload_path) = main_path {
+            url = Some(
+                resnap_rs::upload_to_s3(upload_path, target, output.format.mime_type()).await?,
+            );
+        }
+    }
+
+    let metadata = CaptureMetadata {
+        capture,
+        full_path: full_path.as_deref(),
+        cropped_path: cropped_path.as_deref(),
+        full_sha256: full_sha256.as_deref(),
+        cropped_sha256: cropped_sha256.as_deref(),
+        url: url.as_deref(),
+        ocr_text: ocr_text.as_deref(),
+        timings,
+        timestamp: *time,
+    };
+
+    #[cfg(feature = "webhook")]
+    if let Some(webhook) = &save.webhook {
+        if let Some(post_path) = main_path {
+            resnap_rs::post_to_webhook(
+                post_path,
+                output.format.mime_type(),
+                metadata.to_json(),
+                webhook,
+            )
+            .await?;
+        }
+    }
+
+    if save.json {
+        println!("{}", metadata.to_json());
     } else {
-        log::info!("⚠️ No significant content found in the image");
+        if let Some(path) = main_path {
+            println!("{}", path.display());
+        }
+        if let Some(url) = &url {
+            println!("{}", url);
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address every test puts in `RESNAP_IP`, so they agree when run in parallel
+    const ENV_IP: &str = "10.11.99.1";
+
+    /// Tablet addresses parsed from a command line with `RESNAP_IP` set
+    fn ip_addresses(args: &[&str]) -> Vec<String> {
+        env::set_var("RESNAP_IP", ENV_IP);
+        let cli = Cli::try_parse_from(["resnap-rs"].iter().chain(args)).unwrap();
+        cli.args.ip_address
+    }
+
+    #[test]
+    fn ip_address_falls_back_to_resnap_ip() {
+        assert_eq!(ip_addresses(&[]), [ENV_IP]);
+    }
+
+    #[test]
+    fn ip_address_flag_overrides_resnap_ip() {
+        assert_eq!(ip_addresses(&["-I", "192.168.1.20"]), ["192.168.1.20"]);
+        assert_eq!(
+            ip_addresses(&["--ip-address", "192.168.1.20,192.168.1.21"]),
+            ["192.168.1.20", "192.168.1.21"]
+        );
+    }
+}
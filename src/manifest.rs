@@ -0,0 +1,47 @@
+use crate::ResnapError;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+/// Size and SHA-256 hash of a saved file, for checking it later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDigest {
+    /// Size of the file in bytes
+    pub size: u64,
+    /// SHA-256 hash of the contents, in lowercase hex
+    pub sha256: String,
+}
+
+impl FileDigest {
+    /// Hash the file at `path`
+    pub fn of_file(path: &Path) -> Result<Self, ResnapError> {
+        let data = fs::read(path)?;
+
+        Ok(Self {
+            size: data.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&data)),
+        })
+    }
+}
+
+/// Append a line for `path` to the manifest at `manifest`, creating it if needed
+///
+/// Each line holds the file name, size and SHA-256 hash separated by tabs. The line is written
+/// in one go, so captures from several tablets can share a manifest.
+pub fn append_to_manifest(
+    manifest: &Path,
+    path: &Path,
+    digest: &FileDigest,
+) -> Result<(), ResnapError> {
+    let line = format!("{}\t{}\t{}\n", path.display(), digest.size, digest.sha256);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(manifest)?
+        .write_all(line.as_bytes())?;
+
+    Ok(())
+}
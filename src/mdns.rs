@@ -0,0 +1,49 @@
+use crate::ResnapError;
+use mdns_sd::{HostnameResolutionEvent, ServiceDaemon};
+use std::{net::IpAddr, time::Duration};
+
+/// How long to wait for an answer to an mDNS query
+pub const MDNS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Whether `host` is a `.local` name served by multicast DNS
+pub fn is_mdns_host(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".local")
+}
+
+/// Resolve a `.local` hostname with a multicast DNS query, without the system resolver
+///
+/// IPv4 addresses are preferred, as link-local IPv6 addresses need an interface scope that
+/// isn't known here. Returns `None` when no answer arrives within `timeout`.
+pub async fn resolve_mdns(host: &str, timeout: Duration) -> Result<Option<IpAddr>, ResnapError> {
+    let mdns_error = |e: mdns_sd::Error| ResnapError::Mdns(e.to_string());
+
+    let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+    let hostname = format!("{}.", host.trim_end_matches('.'));
+    let receiver = daemon
+        .resolve_hostname(&hostname, Some(timeout.as_millis() as u64))
+        .map_err(mdns_error)?;
+
+    let mut resolved = None;
+    while let Ok(event) = receiver.recv_async().await {
+        match event {
+            HostnameResolutionEvent::AddressesFound(_, addresses) => {
+                resolved = addresses
+                    .iter()
+                    .find(|addr| addr.is_ipv4())
+                    .or_else(|| addresses.iter().next())
+                    .copied();
+                break;
+            }
+            HostnameResolutionEvent::SearchTimeout(_)
+            | HostnameResolutionEvent::SearchStopped(_) => break,
+            _ => {}
+        }
+    }
+
+    // Shutting down only fails when the daemon is already gone
+    let _ = daemon.shutdown();
+
+    Ok(resolved)
+}
@@ -0,0 +1,176 @@
+use crate::{Capture, Rect, StageTimings};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::{fmt::Write, path::Path};
+
+/// Machine-readable summary of a saved capture
+#[derive(Debug, Clone)]
+pub struct CaptureMetadata<'a> {
+    /// The capture being described
+    pub capture: &'a Capture,
+    /// Where the full screen image was saved, if it was kept
+    pub full_path: Option<&'a Path>,
+    /// Where the cropped image was saved, if one was
+    pub cropped_path: Option<&'a Path>,
+    /// SHA-256 hash of the full screen image, if it was hashed
+    pub full_sha256: Option<&'a str>,
+    /// SHA-256 hash of the cropped image, if it was hashed
+    pub cropped_sha256: Option<&'a str>,
+    /// URL of the uploaded copy, if one was uploaded
+    pub url: Option<&'a str>,
+    /// Text recognized by OCR, if OCR was run
+    pub ocr_text: Option<&'a str>,
+    /// Time spent in each stage of the capture, if it was timed
+    pub timings: Option<&'a StageTimings>,
+    /// When the capture was taken
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CaptureMetadata<'_> {
+    /// Serialize the metadata as a single-line JSON object
+    pub fn to_json(&self) -> String {
+        let detection = self.capture.detection.as_ref();
+
+        let mut json = String::from("{");
+        let _ = write!(
+            json,
+            "\"full_path\":{}",
+            self.full_path
+                .map(|p| json_string(&p.to_string_lossy()))
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"cropped_path\":{}",
+            self.cropped_path
+                .map(|p| json_string(&p.to_string_lossy()))
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"full_sha256\":{}",
+            self.full_sha256
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"cropped_sha256\":{}",
+            self.cropped_sha256
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"url\":{}",
+            self.url
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"ocr_text\":{}",
+            self.ocr_text
+                .map(json_string)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(json, ",\"width\":{}", self.capture.screen.width());
+        let _ = write!(json, ",\"height\":{}", self.capture.screen.height());
+        let _ = write!(
+            json,
+            ",\"bounding_box\":{}",
+            detection
+                .and_then(|d| d.bounding_box)
+                .map(json_rect)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"content_found\":{}",
+            detection
+                .map(|d| d.bounding_box.is_some().to_string())
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"contours_found\":{}",
+            json_count(detection.map(|d| d.contours_found))
+        );
+        let _ = write!(
+            json,
+            ",\"contours_significant\":{}",
+            json_count(detection.map(|d| d.contours_significant))
+        );
+        let _ = write!(
+            json,
+            ",\"model\":{}",
+            json_string(&self.capture.model.to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"battery_percent\":{}",
+            self.capture
+                .battery
+                .map(|battery| battery.to_string())
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"timings_ms\":{}",
+            self.timings
+                .map(json_timings)
+                .unwrap_or_else(|| "null".to_string())
+        );
+        let _ = write!(
+            json,
+            ",\"timestamp\":{}",
+            json_string(&self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true))
+        );
+        json.push('}');
+
+        json
+    }
+}
+
+/// Encode a string as a JSON string literal
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Encode stage timings as an object of milliseconds per stage, plus the total
+fn json_timings(timings: &StageTimings) -> String {
+    let mut json = String::from("{");
+    for (name, duration) in &timings.stages {
+        let _ = write!(json, "{}:{},", json_string(name), duration.as_millis());
+    }
+    let _ = write!(json, "\"total\":{}}}", timings.total().as_millis());
+    json
+}
+
+fn json_rect(rect: Rect) -> String {
+    format!(
+        "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{}}}",
+        rect.x, rect.y, rect.width, rect.height
+    )
+}
+
+fn json_count(count: Option<usize>) -> String {
+    count
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "null".to_string())
+}
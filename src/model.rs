@@ -0,0 +1,303 @@
+use crate::{RemoteExec, ResnapError};
+use std::fmt;
+
+/// reMarkable tablet models with known framebuffer layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Model {
+    /// reMarkable 1
+    Rm1,
+    /// reMarkable 2
+    #[default]
+    Rm2,
+    /// reMarkable Paper Pro
+    Rmpp,
+}
+
+/// Layout of the raw framebuffer, the filters needed to orient it and its contrast curve
+#[derive(Debug, Clone, PartialEq)]
+pub struct FramebufferFormat {
+    /// Width of the raw framebuffer in pixels
+    pub width: usize,
+    /// Height of the raw framebuffer in pixels
+    pub height: usize,
+    /// Number of bytes per pixel
+    pub bytes_per_pixel: usize,
+    /// ffmpeg pixel format of the raw framebuffer
+    pub pixel_format: &'static str,
+    /// ffmpeg filter that rotates the framebuffer into portrait orientation
+    pub transpose: &'static str,
+    /// Default black and white points of the contrast curve, as fractions of full scale
+    pub curve: (f32, f32),
+}
+
+impl FramebufferFormat {
+    /// Layout of the rm2fb shared-memory framebuffer, which emulates the reMarkable 1 screen
+    pub fn rm2fb() -> Self {
+        Self {
+            width: 1404,
+            height: 1872,
+            bytes_per_pixel: 2,
+            pixel_format: "rgb565le",
+            transpose: "null", // Already in portrait orientation
+            curve: (0.0, 1.0),
+        }
+    }
+
+    /// Total size of the framebuffer in bytes
+    pub fn window_bytes(&self) -> usize {
+        self.width * self.height * self.bytes_per_pixel
+    }
+}
+
+impl Model {
+    /// Framebuffer layout of this model
+    ///
+    /// The default contrast curve depends on the pixel range each model uses:
+    ///
+    /// - reMarkable 1: `0/1`, since `rgb565le` spans the full range from black to white
+    /// - reMarkable 2: `0.045/0.06`, since xochitl's `gray16` copy only uses the bottom of the
+    ///   16-bit range
+    /// - reMarkable Paper Pro: `0/1`, since `rgba` spans the full range from black to white
+    pub fn framebuffer(&self) -> FramebufferFormat {
+        match self {
+            Model::Rm1 => FramebufferFormat {
+                width: 1408,
+                height: 1872,
+                bytes_per_pixel: 2,
+                pixel_format: "rgb565le",
+                transpose: "null", // Already in portrait orientation
+                curve: (0.0, 1.0),
+            },
+            Model::Rm2 => FramebufferFormat {
+                width: 1872,
+                height: 1404,
+                bytes_per_pixel: 2,
+                pixel_format: "gray16",
+                transpose: "transpose=2,hflip", // 90° clockwise and horizontal flip
+                curve: (0.045, 0.06),
+            },
+            Model::Rmpp => FramebufferFormat {
+                width: 1632,
+                height: 2154,
+                bytes_per_pixel: 4,
+                pixel_format: "rgba",
+                transpose: "vflip", // Stored bottom-up
+                curve: (0.0, 1.0),
+            },
+        }
+    }
+
+    /// Screen resolution in dots per inch
+    pub fn dpi(&self) -> u32 {
+        match self {
+            Model::Rm1 | Model::Rm2 => 226,
+            Model::Rmpp => 229,
+        }
+    }
+
+    /// Whether the framebuffer holds color rather than grayscale pixels
+    pub fn is_color(&self) -> bool {
+        matches!(self, Model::Rmpp)
+    }
+}
+
+/// Detect the tablet model from the machine name reported by the device
+///
+/// Reads `/sys/devices/soc0/machine`, falling back to `/proc/device-tree/model`.
+pub async fn detect_model(session: &impl RemoteExec) -> Result<Model, ResnapError> {
+    let output = session
+        .run("cat /sys/devices/soc0/machine 2>/dev/null || cat /proc/device-tree/model 2>/dev/null")
+        .await?;
+
+    let machine = String::from_utf8_lossy(&output.stdout)
+        .trim_matches(|c: char| c.is_whitespace() || c == '\0')
+        .to_string();
+
+    parse_machine_name(&machine).ok_or(ResnapError::UnknownModel(machine))
+}
+
+/// Remote command printing the virtual `width,height`, the bits per pixel and the first video
+/// mode of framebuffer `fb`
+fn fb_sysfs_command(fb: u32) -> String {
+    format!(
+        "cat /sys/class/graphics/fb{0}/virtual_size /sys/class/graphics/fb{0}/bits_per_pixel \
+         2>/dev/null; grep -m1 . /sys/class/graphics/fb{0}/modes 2>/dev/null",
+        fb
+    )
+}
+
+/// Read the layout of framebuffer `/dev/fb<fb>` from sysfs, falling back to the model's
+/// default layout
+///
+/// The detected width, height and bytes per pixel replace the defaults only when they fit
+/// the model's pixel format and aren't just the defaults in the other orientation, since
+/// the orientation filter is tied to the default layout. A layout larger than the default is
+/// ignored too, as it can only be the double-buffered virtual screen.
+pub async fn detect_framebuffer_format(
+    session: &impl RemoteExec,
+    model: Model,
+    fb: u32,
+) -> Result<FramebufferFormat, ResnapError> {
+    let default = model.framebuffer();
+
+    let output = session.run(&fb_sysfs_command(fb)).await?;
+
+    let Some((width, height, bytes_per_pixel)) =
+        parse_fb_sysfs(&String::from_utf8_lossy(&output.stdout))
+    else {
+        log::info!(
+            "📏 No framebuffer size in sysfs, using the {} layout",
+            model
+        );
+        return Ok(default);
+    };
+    log::info!(
+        "📏 sysfs reports a {}x{} framebuffer with {}B per pixel",
+        width,
+        height,
+        bytes_per_pixel
+    );
+
+    let same_size = (width, height) == (default.width, default.height)
+        || (width, height) == (default.height, default.width);
+    let too_large = width * height * bytes_per_pixel > default.window_bytes();
+    if same_size || too_large || bytes_per_pixel != default.bytes_per_pixel {
+        if too_large {
+            log::warn!(
+                "⚠️ Detected layout is larger than the {} screen, using its default layout",
+                model
+            );
+        } else if !same_size {
+            log::warn!(
+                "⚠️ Detected layout doesn't fit the {} pixel format, using the {} layout",
+                default.pixel_format,
+                model
+            );
+        }
+        return Ok(default);
+    }
+
+    Ok(FramebufferFormat {
+        width,
+        height,
+        bytes_per_pixel,
+        ..default
+    })
+}
+
+/// Parse `virtual_size` (`width,height`), `bits_per_pixel` and the first line of `modes`
+/// (`U:<xres>x<yres>p-<refresh>`) read from sysfs
+///
+/// The width comes from `virtual_size`, which includes any padding at the end of each row, and
+/// the height from the mode when there is one, since the virtual height also counts the
+/// second buffer of a double-buffered screen.
+fn parse_fb_sysfs(output: &str) -> Option<(usize, usize, usize)> {
+    let mut lines = output.lines().map(str::trim);
+    let (width, virtual_height) = lines.next()?.split_once(',')?;
+    let bits_per_pixel: usize = lines.next()?.parse().ok()?;
+
+    let width: usize = width.trim().parse().ok()?;
+    let height = match lines.next().and_then(parse_fb_mode_height) {
+        Some(height) => height,
+        None => virtual_height.trim().parse().ok()?,
+    };
+    if width == 0 || height == 0 || bits_per_pixel == 0 || bits_per_pixel % 8 != 0 {
+        return None;
+    }
+
+    Some((width, height, bits_per_pixel / 8))
+}
+
+/// Visible height of a video mode such as `U:1404x1872p-85`
+fn parse_fb_mode_height(mode: &str) -> Option<usize> {
+    let (_, resolution) = mode.split_once(':')?;
+    let (_, rest) = resolution.split_once('x')?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..digits].parse().ok()
+}
+
+/// Map a machine name such as `reMarkable 2.0` to its model
+fn parse_machine_name(machine: &str) -> Option<Model> {
+    match machine {
+        m if m.starts_with("reMarkable 1") => Some(Model::Rm1),
+        m if m.starts_with("reMarkable 2") => Some(Model::Rm2),
+        m if m.starts_with("reMarkable Ferrari") || m.contains("Paper Pro") => Some(Model::Rmpp),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Model::Rm1 => "rm1",
+            Model::Rm2 => "rm2",
+            Model::Rmpp => "rmpp",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Progress, Transfer};
+    use std::process::{ExitStatus, Output};
+
+    /// A tablet whose sysfs reports fixed framebuffer attributes
+    struct FakeSysfs(&'static str);
+
+    impl RemoteExec for FakeSysfs {
+        async fn run(&self, command: &str) -> Result<Output, ResnapError> {
+            assert!(command.contains("/sys/class/graphics/fb0/"), "{}", command);
+            Ok(Output {
+                status: ExitStatus::default(),
+                stdout: self.0.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            })
+        }
+
+        async fn read_mem(
+            &self,
+            path: &str,
+            _offset: u64,
+            _len: usize,
+            _transfer: Transfer,
+            _chunk_size: Option<usize>,
+            _compress: bool,
+            _progress: &Progress<'_>,
+        ) -> Result<Vec<u8>, ResnapError> {
+            panic!("unexpected read of {}", path)
+        }
+    }
+
+    #[test]
+    fn takes_the_height_from_the_mode() {
+        let sysfs = "1408,3840\n16\nU:1404x1872p-0\n";
+        assert_eq!(parse_fb_sysfs(sysfs), Some((1408, 1872, 2)));
+    }
+
+    #[test]
+    fn falls_back_to_the_virtual_height_without_a_mode() {
+        assert_eq!(parse_fb_sysfs("1408,3840\n16\n"), Some((1408, 3840, 2)));
+        assert_eq!(parse_fb_sysfs("1408,3840\n"), None);
+    }
+
+    #[tokio::test]
+    async fn ignores_a_double_buffered_virtual_size() {
+        let format = detect_framebuffer_format(&FakeSysfs("1408,3840\n16\n"), Model::Rm1, 0)
+            .await
+            .unwrap();
+        assert_eq!(format, Model::Rm1.framebuffer());
+    }
+
+    #[tokio::test]
+    async fn reads_the_visible_size_of_a_double_buffered_screen() {
+        let sysfs = FakeSysfs("1408,3840\n16\nU:1404x1800p-0\n");
+        let format = detect_framebuffer_format(&sysfs, Model::Rm1, 0)
+            .await
+            .unwrap();
+        assert_eq!((format.width, format.height), (1408, 1800));
+    }
+}
@@ -0,0 +1,38 @@
+use crate::ResnapError;
+use std::{io::ErrorKind, path::Path, process::Command};
+
+/// Language used for OCR when none is configured
+pub const DEFAULT_OCR_LANG: &str = "eng";
+
+/// Recognize the text in a saved image with Tesseract
+///
+/// Runs `tesseract <path> stdout -l <lang>`; `lang` is a Tesseract language code such as
+/// `eng` or `deu+eng`, whose traineddata must be installed.
+pub fn recognize_text(path: &Path, lang: &str) -> Result<String, ResnapError> {
+    let output = Command::new("tesseract")
+        .arg(path)
+        .arg("stdout")
+        .args(["-l", lang])
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => ResnapError::Ocr(
+                "tesseract was not found on PATH; install it from \
+                 https://tesseract-ocr.github.io/tessdoc/Installation.html"
+                    .to_string(),
+            ),
+            _ => e.into(),
+        })?;
+
+    if !output.status.success() {
+        return Err(ResnapError::Ocr(format!(
+            "tesseract exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    log::info!("🔤 Recognized {} characters of text", text.chars().count());
+
+    Ok(text)
+}
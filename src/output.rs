@@ -0,0 +1,256 @@
+use crate::{Model, ResnapError};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Utc,
+};
+use flate2::Crc;
+use image::{
+    codecs::{
+        jpeg::{JpegEncoder, PixelDensity},
+        png::{CompressionType, FilterType, PngEncoder},
+        webp::WebPEncoder,
+    },
+    DynamicImage, RgbImage,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Cursor, Write},
+    path::Path,
+};
+
+/// Image formats the captured screens can be saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Lossless PNG with transparency
+    #[default]
+    Png,
+    /// Lossy JPEG, transparency is dropped
+    Jpg,
+    /// Lossless WebP with transparency
+    Webp,
+}
+
+impl OutputFormat {
+    /// File extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    /// MIME type of files in this format
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// Trade-off between PNG file size and encoding speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PngCompression {
+    /// Fastest encoding, larger files
+    Fast,
+    /// Balance of speed and size
+    #[default]
+    Default,
+    /// Smallest files, slowest encoding, for archiving
+    Best,
+}
+
+impl PngCompression {
+    /// Encoder setting for this level
+    fn compression_type(self) -> CompressionType {
+        match self {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        }
+    }
+}
+
+/// Settings for encoding saved images
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Image format to encode
+    pub format: OutputFormat,
+    /// JPEG quality from 1 to 100
+    pub quality: u8,
+    /// PNG compression level
+    pub png_compression: PngCompression,
+    /// Invert the colors of saved images
+    pub invert: bool,
+    /// Resolution recorded in PNG and JPEG files, in dots per inch
+    pub dpi: Option<u32>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Png,
+            quality: 90,
+            png_compression: PngCompression::Default,
+            invert: false,
+            dpi: None,
+        }
+    }
+}
+
+/// Encode an image to `path` using the configured format
+///
+/// The configured DPI is recorded in a `pHYs` chunk for PNG and the JFIF density for JPEG.
+pub fn save_image(
+    img: &DynamicImage,
+    path: &Path,
+    opts: &OutputOptions,
+) -> Result<(), ResnapError> {
+    write_image(img, &mut BufWriter::new(File::create(path)?), opts)
+}
+
+/// Encode an image to `writer` using the configured format, as [`save_image`] does to a file
+pub fn write_image<W: Write>(
+    img: &DynamicImage,
+    writer: &mut W,
+    opts: &OutputOptions,
+) -> Result<(), ResnapError> {
+    let inverted;
+    let img = if opts.invert {
+        let mut copy = img.clone();
+        copy.invert();
+        inverted = copy;
+        &inverted
+    } else {
+        img
+    };
+
+    match opts.format {
+        OutputFormat::Png => match opts.dpi {
+            Some(dpi) => {
+                let mut png = Vec::new();
+                img.write_with_encoder(png_encoder(Cursor::new(&mut png), opts))?;
+                writer.write_all(&with_phys_chunk(&png, dpi))?;
+            }
+            None => img.write_with_encoder(png_encoder(&mut *writer, opts))?,
+        },
+        // JPEG has no alpha channel or 16-bit support
+        OutputFormat::Jpg => {
+            let mut encoder = JpegEncoder::new_with_quality(&mut *writer, opts.quality);
+            if let Some(dpi) = opts.dpi {
+                let dpi = dpi.min(u32::from(u16::MAX)) as u16;
+                encoder.set_pixel_density(PixelDensity::dpi(dpi));
+            }
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?
+        }
+        // The WebP encoder only handles 8-bit images
+        OutputFormat::Webp => DynamicImage::ImageRgba8(img.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut *writer))?,
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Drop the alpha channel of an image by compositing it onto white, as for cropped captures
+/// in formats without transparency
+pub(crate) fn flatten_onto_white(img: &DynamicImage) -> RgbImage {
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let over_white = |c: u8| ((u16::from(c) * u16::from(a)) / 255 + 255 - u16::from(a)) as u8;
+        image::Rgb([over_white(r), over_white(g), over_white(b)])
+    })
+}
+
+/// PNG encoder with the configured compression level
+fn png_encoder<W: Write>(writer: W, opts: &OutputOptions) -> PngEncoder<W> {
+    PngEncoder::new_with_quality(
+        writer,
+        opts.png_compression.compression_type(),
+        FilterType::Adaptive,
+    )
+}
+
+/// Length of the PNG signature plus the IHDR chunk, which must come first
+const PNG_HEADER_LEN: usize = 8 + 4 + 4 + 13 + 4;
+
+/// Insert a `pHYs` chunk recording `dpi` right after the IHDR chunk of an encoded PNG
+fn with_phys_chunk(png: &[u8], dpi: u32) -> Vec<u8> {
+    let pixels_per_meter = (f64::from(dpi) / 0.0254).round() as u32;
+
+    let mut chunk = Vec::with_capacity(4 + 9);
+    chunk.extend_from_slice(b"pHYs");
+    chunk.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    chunk.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    chunk.push(1); // Unit is the meter
+
+    let mut crc = Crc::new();
+    crc.update(&chunk);
+
+    let mut out = Vec::with_capacity(png.len() + 4 + chunk.len() + 4);
+    out.extend_from_slice(&png[..PNG_HEADER_LEN]);
+    out.extend_from_slice(&9u32.to_be_bytes());
+    out.extend_from_slice(&chunk);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+    out.extend_from_slice(&png[PNG_HEADER_LEN..]);
+    out
+}
+
+/// Template for naming output files, without the extension
+///
+/// Supports strftime tokens such as `%Y-%m-%d`, `{model}` for the tablet model and `{n}`
+/// for a counter that picks the first unused number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl NameTemplate {
+    /// Template used when none is configured
+    pub const DEFAULT: &'static str = "%m-%d-%Y-%H-%M-%S-remarkable-screen";
+
+    /// Parse a template, rejecting invalid strftime tokens
+    pub fn new(template: &str) -> Result<Self, ResnapError> {
+        if template.is_empty() {
+            return Err(ResnapError::InvalidConfig(
+                "name template must not be empty".to_string(),
+            ));
+        }
+
+        if StrftimeItems::new(template).any(|item| matches!(item, Item::Error)) {
+            return Err(ResnapError::InvalidConfig(format!(
+                "invalid strftime token in name template {:?}",
+                template
+            )));
+        }
+
+        Ok(Self(template.to_string()))
+    }
+
+    /// The template as given
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the template contains the `{n}` counter
+    pub fn uses_counter(&self) -> bool {
+        self.0.contains("{n}")
+    }
+
+    /// Render the template for a capture taken at `time`
+    pub fn render(&self, time: &DateTime<Utc>, model: Model, n: u32) -> String {
+        let template = self
+            .0
+            .replace("{model}", &model.to_string())
+            .replace("{n}", &n.to_string());
+
+        time.format(&template).to_string()
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{output::flatten_onto_white, ResnapError};
+use flate2::{write::ZlibEncoder, Compression};
+use image::DynamicImage;
+use std::{fs, io::Write, path::Path};
+
+/// One image page, compressed and ready to be written
+#[derive(Debug, Clone)]
+struct PdfPage {
+    width: u32,
+    height: u32,
+    /// Page size in points, from the image size and its resolution
+    size: (f32, f32),
+    data: Vec<u8>,
+}
+
+/// A PDF built up one image per page, written out with [`PdfDocument::save`]
+///
+/// Each page is sized to its image at the given resolution. Transparent pixels, as in cropped
+/// captures, are flattened onto white.
+#[derive(Debug, Clone, Default)]
+pub struct PdfDocument {
+    pages: Vec<PdfPage>,
+}
+
+impl PdfDocument {
+    /// Start an empty document
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `img` as a new page printed at `dpi`
+    pub fn add_page(&mut self, img: &DynamicImage, dpi: u32) -> Result<(), ResnapError> {
+        let rgb = flatten_onto_white(img);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(rgb.as_raw())?;
+
+        let points = |pixels: u32| pixels as f32 * 72.0 / dpi.max(1) as f32;
+        self.pages.push(PdfPage {
+            width: rgb.width(),
+            height: rgb.height(),
+            size: (points(rgb.width()), points(rgb.height())),
+            data: encoder.finish()?,
+        });
+
+        Ok(())
+    }
+
+    /// Number of pages added so far
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Whether no pages have been added
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Write the document to `path`
+    pub fn save(&self, path: &Path) -> Result<(), ResnapError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Serialize the document: catalog, page tree, then a page, content stream and image per
+    /// page, followed by the cross-reference table
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::new();
+        let mut object = |pdf: &mut Vec<u8>, body: &[u8]| {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n", offsets.len()).as_bytes());
+            pdf.extend(body);
+            pdf.extend(b"\nendobj\n");
+        };
+
+        // Objects 1 and 2, then three per page starting at 3
+        let kids: Vec<String> = (0..self.pages.len())
+            .map(|i| format!("{} 0 R", 3 + i * 3))
+            .collect();
+        object(&mut pdf, b"<< /Type /Catalog /Pages 2 0 R >>");
+        object(
+            &mut pdf,
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                self.pages.len()
+            )
+            .as_bytes(),
+        );
+
+        for (i, page) in self.pages.iter().enumerate() {
+            let page_id = 3 + i * 3;
+            let (width, height) = page.size;
+            object(
+                &mut pdf,
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+                     /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+                    width,
+                    height,
+                    page_id + 2,
+                    page_id + 1
+                )
+                .as_bytes(),
+            );
+
+            let content = format!("q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q", width, height);
+            object(
+                &mut pdf,
+                format!(
+                    "<< /Length {} >>\nstream\n{}\nendstream",
+                    content.len(),
+                    content
+                )
+                .as_bytes(),
+            );
+
+            let mut image = format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+                 /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>\nstream\n",
+                page.width,
+                page.height,
+                page.data.len()
+            )
+            .into_bytes();
+            image.extend(&page.data);
+            image.extend(b"\nendstream");
+            object(&mut pdf, &image);
+        }
+
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+        for offset in &offsets {
+            pdf.extend(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                offsets.len() + 1,
+                xref
+            )
+            .as_bytes(),
+        );
+
+        pdf
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/// Stages of a capture, reported to progress callbacks in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Opening the SSH session to the tablet
+    Connecting,
+    /// Finding the `xochitl` process and its framebuffer address
+    ResolvingPid,
+    /// Copying `bytes` bytes of framebuffer off the tablet
+    ReadingFramebuffer { bytes: usize },
+    /// `read` of the `total` framebuffer bytes have arrived, reported repeatedly during the copy
+    Transferring { read: usize, total: usize },
+    /// Converting the raw framebuffer into an image
+    Decoding,
+    /// Searching the screen image for handwritten content
+    DetectingContours,
+    /// Cropping the screen image to the content
+    Cropping,
+    /// The capture finished
+    Done,
+}
+
+/// Callback told about each [`Stage`] as a capture reaches it
+pub type Progress<'a> = dyn Fn(Stage) + Sync + 'a;
+
+/// Progress callback that ignores every stage
+pub(crate) fn no_progress(_: Stage) {}
+
+/// Time spent in each stage of a capture, recorded from its progress reports
+///
+/// Each stage lasts until the next one is reported, or until [`StageTimings::finish`].
+#[derive(Debug, Clone, Default)]
+pub struct StageTimings {
+    /// Name and duration of each finished stage, in order
+    pub stages: Vec<(&'static str, Duration)>,
+    current: Option<(&'static str, Instant)>,
+}
+
+impl StageTimings {
+    /// Start timing `stage`, ending the one before it
+    ///
+    /// [`Stage::Transferring`] is part of reading the framebuffer and [`Stage::Done`] ends the
+    /// last stage. [`Stage::Connecting`] starts over, so only the last of several attempts is
+    /// timed.
+    pub fn record(&mut self, stage: Stage) {
+        let name = match stage {
+            Stage::Connecting => {
+                *self = Self::default();
+                "connect"
+            }
+            Stage::ResolvingPid => "locate",
+            Stage::ReadingFramebuffer { .. } => "transfer",
+            Stage::Transferring { .. } => return,
+            Stage::Decoding => "decode",
+            Stage::DetectingContours => "detect",
+            Stage::Cropping => "crop",
+            Stage::Done => return self.finish(),
+        };
+
+        self.finish();
+        self.current = Some((name, Instant::now()));
+    }
+
+    /// End the stage being timed, if any
+    pub fn finish(&mut self) {
+        if let Some((name, start)) = self.current.take() {
+            self.stages.push((name, start.elapsed()));
+        }
+    }
+
+    /// Time spent in all finished stages
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, duration)| *duration).sum()
+    }
+}
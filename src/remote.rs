@@ -0,0 +1,92 @@
+use crate::{
+    framebuffer::{dump_framebuffer, read_framebuffer_sftp, read_with_progress},
+    Progress, ResnapError, Transfer,
+};
+use openssh::Session;
+use std::{future::Future, io::SeekFrom, process::Output};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    process::Command,
+};
+
+/// Connection to a tablet that the capture pipeline runs commands and reads memory over
+///
+/// Implemented for [`openssh::Session`]. The capture functions accept any implementation, so a
+/// fake tablet can stand in for tests and other transports can be added without touching the
+/// pipeline.
+pub trait RemoteExec: Sync {
+    /// Run `cmd` with `sh -c` on the tablet and collect its output
+    fn run(&self, cmd: &str) -> impl Future<Output = Result<Output, ResnapError>> + Send;
+
+    /// Read up to `len` bytes of the file at `path` on the tablet, starting `offset` bytes in
+    ///
+    /// `path` is usually a process's `/proc/<pid>/mem`, so the data comes back short when the
+    /// process has gone away. `transfer`, `chunk_size` and `compress` pick how the data is
+    /// copied off the tablet, and `progress` sees [`Stage::Transferring`](crate::Stage::Transferring) as it
+    /// arrives.
+    fn read_mem(
+        &self,
+        path: &str,
+        offset: u64,
+        len: usize,
+        transfer: Transfer,
+        chunk_size: Option<usize>,
+        compress: bool,
+        progress: &Progress<'_>,
+    ) -> impl Future<Output = Result<Vec<u8>, ResnapError>> + Send;
+}
+
+impl RemoteExec for Session {
+    async fn run(&self, cmd: &str) -> Result<Output, ResnapError> {
+        Ok(self.command("sh").arg("-c").arg(cmd).output().await?)
+    }
+
+    async fn read_mem(
+        &self,
+        path: &str,
+        offset: u64,
+        len: usize,
+        transfer: Transfer,
+        chunk_size: Option<usize>,
+        compress: bool,
+        progress: &Progress<'_>,
+    ) -> Result<Vec<u8>, ResnapError> {
+        match transfer {
+            Transfer::Dd => {
+                dump_framebuffer(self, path, offset, len, chunk_size, compress, progress).await
+            }
+            Transfer::Sftp => read_framebuffer_sftp(self, path, offset, len, progress).await,
+        }
+    }
+}
+
+/// The machine resnap runs on, for capturing when it runs on the tablet itself
+///
+/// Commands run in a local shell and memory is read straight from the file, so the transfer
+/// method, chunk size and compression are ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalExec;
+
+impl RemoteExec for LocalExec {
+    async fn run(&self, cmd: &str) -> Result<Output, ResnapError> {
+        Ok(Command::new("sh").arg("-c").arg(cmd).output().await?)
+    }
+
+    async fn read_mem(
+        &self,
+        path: &str,
+        offset: u64,
+        len: usize,
+        _transfer: Transfer,
+        _chunk_size: Option<usize>,
+        _compress: bool,
+        progress: &Progress<'_>,
+    ) -> Result<Vec<u8>, ResnapError> {
+        log::info!("📤 Reading framebuffer data from {}...", path);
+        let mut file = File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        read_with_progress(file.take(len as u64), len, progress).await
+    }
+}
@@ -0,0 +1,50 @@
+use crate::ResnapError;
+use std::{future::Future, time::Duration};
+
+/// How often and how patiently transient failures are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry, doubled after every further attempt
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `op`, retrying with exponential backoff while it fails with a transient error
+    pub async fn run<T, F, Fut>(&self, mut op: F) -> Result<T, ResnapError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ResnapError>>,
+    {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+
+        loop {
+            match op().await {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "🔁 Attempt {}/{} failed: {}; retrying in {}ms",
+                        attempt,
+                        self.retries + 1,
+                        e,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+}
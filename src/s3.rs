@@ -0,0 +1,104 @@
+use crate::ResnapError;
+use aws_sdk_s3::{primitives::ByteStream, Client};
+use std::{path::Path, str::FromStr};
+
+/// Bucket and key prefix that captures are uploaded to, parsed from `bucket/prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Target {
+    /// Name of the bucket
+    pub bucket: String,
+    /// Key prefix the file name is appended to, without a trailing slash
+    pub prefix: String,
+}
+
+impl S3Target {
+    /// Object key for a file uploaded under this target
+    pub fn key(&self, file_name: &str) -> String {
+        if self.prefix.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{}/{}", self.prefix, file_name)
+        }
+    }
+}
+
+impl FromStr for S3Target {
+    type Err = ResnapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("s3://").unwrap_or(s);
+        let (bucket, prefix) = s.split_once('/').unwrap_or((s, ""));
+
+        if bucket.is_empty() {
+            return Err(ResnapError::InvalidConfig(format!(
+                "S3 target {:?} has no bucket",
+                s
+            )));
+        }
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+}
+
+/// Upload a saved file to S3 and return the URL of the new object
+///
+/// The endpoint, region and credentials are read from the standard `AWS_*` environment
+/// variables and config files; setting `AWS_ENDPOINT_URL` targets an S3-compatible store.
+/// The file is streamed from disk rather than read into memory.
+pub async fn upload_to_s3(
+    path: &Path,
+    target: &S3Target,
+    content_type: &str,
+) -> Result<String, ResnapError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| ResnapError::S3(format!("{} has no file name", path.display())))?
+        .to_string_lossy();
+    let key = target.key(&file_name);
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    let region = sdk_config
+        .region()
+        .map(|r| r.to_string())
+        .unwrap_or_else(|| "us-east-1".to_string());
+    let endpoint = sdk_config.endpoint_url().map(str::to_string);
+
+    // Most S3-compatible stores don't serve virtual-hosted bucket names
+    let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+        .force_path_style(endpoint.is_some())
+        .build();
+    let client = Client::from_conf(s3_config);
+
+    let body = ByteStream::from_path(path)
+        .await
+        .map_err(|e| ResnapError::S3(e.to_string()))?;
+
+    client
+        .put_object()
+        .bucket(&target.bucket)
+        .key(&key)
+        .content_type(content_type)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| ResnapError::S3(aws_sdk_s3::error::DisplayErrorContext(e).to_string()))?;
+
+    let url = match endpoint {
+        Some(endpoint) => format!(
+            "{}/{}/{}",
+            endpoint.trim_end_matches('/'),
+            target.bucket,
+            key
+        ),
+        None => format!(
+            "https://{}.s3.{}.amazonaws.com/{}",
+            target.bucket, region, key
+        ),
+    };
+    log::info!("☁️ Uploaded {} to {}", path.display(), url);
+
+    Ok(url)
+}
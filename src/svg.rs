@@ -0,0 +1,67 @@
+use crate::Rect;
+use imageproc::point::Point;
+use std::fmt::Write;
+
+/// Styling of the paths in an SVG export
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// Stroke color, any SVG color value
+    pub stroke: String,
+    /// Stroke width in pixels
+    pub stroke_width: f32,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            stroke: "black".to_string(),
+            stroke_width: 1.0,
+        }
+    }
+}
+
+/// Render contours as an SVG document with one `<path>` per contour
+///
+/// The document is sized to `bounds` and the points are translated so its top-left corner is
+/// the origin.
+pub fn contours_to_svg(contours: &[Vec<Point<i32>>], bounds: Rect, opts: &SvgOptions) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = bounds.width,
+        h = bounds.height
+    );
+    let _ = writeln!(
+        svg,
+        r#"<g fill="none" stroke="{}" stroke-width="{}" stroke-linejoin="round">"#,
+        escape_attribute(&opts.stroke),
+        opts.stroke_width
+    );
+
+    for points in contours.iter().filter(|points| !points.is_empty()) {
+        let mut data = String::new();
+        for (i, point) in points.iter().enumerate() {
+            let command = if i == 0 { 'M' } else { 'L' };
+            let _ = write!(
+                data,
+                "{}{} {} ",
+                command,
+                point.x - bounds.x as i32,
+                point.y - bounds.y as i32
+            );
+        }
+        data.push('Z');
+        let _ = writeln!(svg, r#"<path d="{}"/>"#, data);
+    }
+
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
@@ -0,0 +1,92 @@
+use crate::ResnapError;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    multipart::{Form, Part},
+};
+use std::path::Path;
+
+/// Where captures are POSTed and which extra headers are sent
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    /// URL the captures are POSTed to
+    pub url: reqwest::Url,
+    /// Extra headers sent with every request, such as an `Authorization` token
+    pub headers: HeaderMap,
+}
+
+impl WebhookOptions {
+    /// Parse the webhook URL and headers given as `key:value`
+    pub fn new(url: &str, headers: &[String]) -> Result<Self, ResnapError> {
+        let url = reqwest::Url::parse(url).map_err(|e| {
+            ResnapError::InvalidConfig(format!("invalid webhook URL {:?}: {}", url, e))
+        })?;
+
+        let mut header_map = HeaderMap::new();
+        for header in headers {
+            let invalid = || {
+                ResnapError::InvalidConfig(format!(
+                    "invalid webhook header {:?}, expected key:value",
+                    header
+                ))
+            };
+            let (key, value) = header.split_once(':').ok_or_else(invalid)?;
+            let key = HeaderName::from_bytes(key.trim().as_bytes()).map_err(|_| invalid())?;
+            let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+            header_map.append(key, value);
+        }
+
+        Ok(Self {
+            url,
+            headers: header_map,
+        })
+    }
+}
+
+/// POST a saved image and its JSON metadata to a webhook as `multipart/form-data`
+///
+/// The image is sent in the `image` field and the metadata in the `metadata` field.
+/// A response outside the 2xx range fails with [`ResnapError::WebhookStatus`].
+pub async fn post_to_webhook(
+    path: &Path,
+    content_type: &str,
+    metadata_json: String,
+    opts: &WebhookOptions,
+) -> Result<(), ResnapError> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let image = tokio::fs::read(path).await?;
+
+    let form = Form::new()
+        .part(
+            "metadata",
+            Part::text(metadata_json)
+                .mime_str("application/json")
+                .map_err(|e| ResnapError::Webhook(e.to_string()))?,
+        )
+        .part(
+            "image",
+            Part::bytes(image)
+                .file_name(file_name)
+                .mime_str(content_type)
+                .map_err(|e| ResnapError::Webhook(e.to_string()))?,
+        );
+
+    let response = reqwest::Client::new()
+        .post(opts.url.clone())
+        .headers(opts.headers.clone())
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| ResnapError::Webhook(e.to_string()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(ResnapError::WebhookStatus(status.as_u16()));
+    }
+
+    log::info!("📨 Posted {} to {}", path.display(), opts.url);
+
+    Ok(())
+}